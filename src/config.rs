@@ -0,0 +1,933 @@
+use image::ImageFormat;
+use reqwest::Url;
+use reqwest_middleware::ClientWithMiddleware;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::{
+    parse_hex_color, resource_kind, Compression, HashFormat, KeyCase, OutputFormat,
+    PlaceholderMode, ResizeFilter, Resource, SyncEvent, SyncPhase, TlsErrorMode, IMAGE_SIZES,
+    WARFRAME_CONTENT_URL, WARFRAME_ORIGIN_URL,
+};
+
+/// Callback invoked after a resource has been successfully downloaded and saved.
+///
+/// Receives the resource descriptor and the raw content as returned by the content server.
+/// The callback runs inline on the download task, so long-running work should be handed off
+/// (e.g. through a channel or `tokio::spawn`) rather than performed directly.
+pub type ResourceCallback = Arc<dyn Fn(&Resource, &[u8]) + Send + Sync>;
+
+/// The order in which changed resources are dispatched for download.
+/// - `Fifo`: In index order.
+/// - `LargestFirst`: Largest exports first (by `Content-Length`), so big downloads don't straggle at the end.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Schedule {
+    #[default]
+    Fifo,
+    LargestFirst,
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "fifo" => Ok(Schedule::Fifo),
+            "largest-first" => Ok(Schedule::LargestFirst),
+            other => Err(format!("Unknown schedule: {}", other)),
+        }
+    }
+}
+
+/// Configuration for a sync run.
+///
+/// Can be deserialized from a TOML or JSON config file using the same field names; any missing
+/// fields use their defaults, and `max_runtime`, `flush_interval`, `loop_interval`, `download_timeout` and
+/// `ramp_up` are given in seconds as `max_runtime_secs`, `flush_interval_secs`, `loop_interval_secs`,
+/// `download_timeout_secs` and `ramp_up_secs`.
+/// - `output_dir`: The directory where exports, images, and hash files are stored.
+/// - `export_dir`: An optional override for where exports are stored (default: `{output_dir}/export`).
+/// - `image_dir`: An optional override for where images are stored (default: `{output_dir}/image`).
+/// - `origin_url`: The Warframe origin server, used to fetch the export index.
+/// - `content_url`: The Warframe content server, used to fetch exports and images.
+/// - `export_url`: An optional override of `content_url` for exports.
+/// - `image_url`: An optional override of `content_url` for images.
+/// - `extra_query`: Query parameters appended to every request URL, e.g. `token=abc&v=1`.
+/// - `proxy_token`: An optional token sent as `X-Proxy-Token` with origin requests.
+/// - `language`: The language of the export index to mirror, e.g. `en`.
+/// - `languages`: Several languages to mirror concurrently, each into `{output_dir}/{language}`. Overrides `language`.
+/// - `strip_metadata`: Whether to re-encode images that are already 512x512, dropping any metadata chunks.
+/// - `optimize_grayscale`: Whether to store images whose color channels are all equal as grayscale, keeping alpha.
+/// - `flatten_background`: An optional `[r, g, b]` color to composite images over, producing opaque output.
+/// - `output_format`: The default encoding for image outputs.
+/// - `output_formats`: Several encodings to write every size in side by side, e.g. during a migration. Overrides `output_format`.
+/// - `size_formats`: Per-size overrides of `output_format`, keyed by size (`512` is the original).
+/// - `size_dirs`: Per-size overrides of the directory resized outputs are written to, otherwise `{size}x{size}`.
+/// - `size_filters`: Per-size overrides of the resize filter, otherwise chosen by `filter_for`.
+/// - `sharpen_amount`: The sigma of an unsharp mask applied to resized variants, if set.
+/// - `size_sharpen`: Per-size overrides of `sharpen_amount`, where `0` disables it (`512` is the original, which is only sharpened here).
+/// - `dpi_sizes`: Extra variants for `srcset`, keyed by pixel density (e.g. `2` for `@2x`), with their size.
+/// - `lazy_sizes`: Whether to only store the 512x512 originals, leaving resized variants to `serve`.
+/// - `only_sizes`: The sizes to rewrite for every current image on this run (`512` is the original), leaving other variants untouched, or empty to write all. Changed images are always written in full.
+/// - `prune_orphan_sizes`: Whether to remove size directories of the image directory whose size is not in `IMAGE_SIZES`.
+/// - `max_concurrent_downloads`: How many resources may be downloading at once, or `0` for no limit.
+/// - `adaptive_concurrency`: Whether to lower the concurrent downloads while many fail, and raise them back as they succeed.
+/// - `adaptive_failure_rate`: The share of failed downloads, from 0 to 1, above which `adaptive_concurrency` backs off.
+/// - `ramp_up`: How long to take raising the concurrent downloads from one to `max_concurrent_downloads`, or zero to start at the limit.
+/// - `download_timeout`: An optional deadline for each download, including reading its content.
+/// - `max_concurrent_decode`: How many images may be decoded and resized at once, defaulting to the number of CPUs.
+/// - `resize_parallelism`: How many resizes of a single image may run in parallel.
+/// - `write_parallelism`: How many outputs of a single image may be written at once.
+/// - `allowed_image_formats`: The source image formats to process (e.g. `png`), or empty to allow all.
+/// - `verify_outputs`: Whether to decode every written image again before recording its hash.
+/// - `compute_phash`: Whether to compute a perceptual hash of each image, stored in `image_hash.json`.
+/// - `store_dimensions`: Whether to store the dimensions of each original image in `image_hash.json`.
+/// - `max_megapixels`: The largest source image, in megapixels, that will be decoded and resized.
+/// - `split_animation_frames`: Whether to also write each frame of animated images and sprite strips, with its resized variants.
+/// - `animation_frame_size`: The width and height of the frames sprite strips are split into, if they're split.
+/// - `placeholders`: Whether blank placeholder images are kept, skipped, or skipped with a marker file.
+/// - `aliases`: Legacy names to also write outputs under, keyed by the current output name.
+/// - `aliases_file`: A JSON file of additional `aliases`, merged in when a run starts.
+/// - `export_filters`: jq filters that transform exports before they're written, keyed by export kind.
+/// - `export_filters_file`: A JSON file of additional `export_filters`, merged in when a run starts.
+/// - `version_check_url`: An optional URL reporting the upstream content version, checked before the export index.
+/// - `manifest_file`: A local `ExportManifest.json` to process images from, skipping the export index and exports.
+/// - `phases`: The phases a run goes through. Without `Exports`, images are processed from the stored manifest.
+/// - `export_unwrap`: Whether to write the inner value of exports that are a single-key object.
+/// - `export_extensions`: The extensions of export index entries to process, e.g. `json`; others are skipped.
+/// - `key_case`: The naming convention export JSON keys are rewritten to.
+/// - `emit_json_patch`: Whether to write a JSON Patch from the previous version of each changed export.
+/// - `emit_ndjson`: Whether to also write exports as newline-delimited JSON.
+/// - `store_compressed`: The compression export outputs are stored with, which adds a suffix such as `.gz` to their names.
+/// - `combined_exports`: The export kinds (e.g. `weapons`) to merge into `combined.json`, or empty to skip it.
+/// - `emit_image_meta`: Whether to write a `.meta.json` sidecar next to each image, with its source URL and fetch time.
+/// - `emit_inventory`: Whether to write `inventory.csv`, listing every tracked resource.
+/// - `emit_changelog`: Whether to append the added and updated resources of each run to `CHANGELOG.md`.
+/// - `emit_names_list`: Whether to write `names.txt`, listing the unique name of every manifest item, when the manifest changes.
+/// - `emit_metrics`: Whether to write `metrics.prom`, for a Prometheus textfile collector.
+/// - `notice`: Attribution text to write as `NOTICE.txt` after every successful run.
+/// - `notice_file`: A file to copy as `NOTICE.txt` after every successful run, taking precedence over `notice`.
+/// - `emit_delta_dir`: Whether to also copy the outputs written by each run into `{output_dir}/delta/{run start}`.
+/// - `min_index_entries`: The fewest entries a new export index may have, when more are tracked.
+/// - `track_index_hash`: Whether to hash the decompressed export index and compare it to the last run's, kept in `version.json`.
+/// - `track_index_order`: Whether to keep the order of the export index in `version.json`, reporting when it changes.
+/// - `hash_format`: The encoding of `export_hash` and `image_hash`, which also sets their extension.
+/// - `verify_content_on_start`: Whether to check stored images against their content hashes when a run starts, downloading damaged ones again.
+/// - `revalidate_unchanged`: Whether to request resources whose hash is unchanged with their stored ETag, instead of skipping them.
+/// - `rebuild_hash_from_files`: Whether to rebuild missing hash maps from the outputs that exist, instead of downloading everything again.
+/// - `recover_corrupt_state`: Whether to back up hash maps and `version.json` that fail to load and start from empty state, instead of failing.
+/// - `max_failures`: How many downloads may fail before the rest of the run is aborted, or `0` for no limit.
+/// - `min_free_bytes`: If set, how many bytes must be available on the output volume for a run to start.
+/// - `force`: Whether to download and regenerate every resource, ignoring stored hashes and ETags, and the index ETag.
+/// - `flush_every`: Save hash maps after this many downloads complete, or `0` to only use `flush_interval`.
+/// - `flush_interval`: Save hash maps once this much time has passed since they were last saved, or zero to disable.
+/// - `batch_size`: Process images in batches of this many manifest items, checkpointing after each, or `0` to disable.
+/// - `max_batches_per_run`: Stop after this many batches, resuming from the checkpoint next run, or `0` for no limit.
+/// - `keep_run_logs`: How many run logs to keep in `{output_dir}/logs`, or `0` to not write them.
+/// - `schedule`: The order in which changed exports are dispatched.
+/// - `retry_statuses`: The HTTP status codes that are retried, e.g. `503`.
+/// - `tls_error`: Whether TLS and certificate errors are retried, or fail immediately.
+/// - `connect_retries`: How many more times the export index is requested when it fails to resolve or connect, with backoff.
+/// - `spawn_delay`: How long to wait between dispatching successive downloads.
+/// - `max_runtime`: An optional time budget; once exceeded, no new downloads are started.
+/// - `loop_interval`: If set, the binary runs indefinitely, waiting this long between runs.
+/// - `http_cache_dir`: Where to cache HTTP responses, if set (requires the `http-cache` feature).
+/// - `http_cache_mode`: How the HTTP cache is used, e.g. `default` or `force-cache` (requires the `http-cache` feature).
+/// - `health_port`: The port to serve `/healthz` and `/metrics` on during runs, if set (requires the `serve` feature).
+/// - `client`: An optional pre-built HTTP client to use instead of the default retrying client.
+/// - `on_resource`: An optional callback invoked for each successfully downloaded resource.
+/// - `events`: An optional channel that receives a `SyncEvent` as downloads start, complete, or fail.
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyncConfig {
+    pub output_dir: String,
+    pub export_dir: Option<String>,
+    pub image_dir: Option<String>,
+    pub origin_url: String,
+    pub content_url: String,
+    pub export_url: Option<String>,
+    pub image_url: Option<String>,
+    pub extra_query: String,
+    pub proxy_token: String,
+    pub language: String,
+    pub languages: Vec<String>,
+    pub strip_metadata: bool,
+    pub optimize_grayscale: bool,
+    #[serde(deserialize_with = "deserialize_hex_color")]
+    pub flatten_background: Option<[u8; 3]>,
+    pub output_format: OutputFormat,
+    pub output_formats: Vec<OutputFormat>,
+    pub size_formats: BTreeMap<u32, OutputFormat>,
+    pub size_dirs: BTreeMap<u32, String>,
+    pub size_filters: BTreeMap<u32, ResizeFilter>,
+    pub sharpen_amount: Option<f32>,
+    pub size_sharpen: BTreeMap<u32, f32>,
+    pub dpi_sizes: BTreeMap<u32, u32>,
+    pub lazy_sizes: bool,
+    pub only_sizes: Vec<u32>,
+    pub prune_orphan_sizes: bool,
+    pub max_concurrent_downloads: usize,
+    pub adaptive_concurrency: bool,
+    pub adaptive_failure_rate: f64,
+    #[serde(rename = "ramp_up_secs", deserialize_with = "deserialize_interval")]
+    pub ramp_up: Duration,
+    #[serde(
+        rename = "download_timeout_secs",
+        deserialize_with = "deserialize_secs"
+    )]
+    pub download_timeout: Option<Duration>,
+    pub max_concurrent_decode: usize,
+    pub resize_parallelism: usize,
+    pub write_parallelism: usize,
+    pub allowed_image_formats: Vec<String>,
+    pub verify_outputs: bool,
+    pub compute_phash: bool,
+    pub store_dimensions: bool,
+    pub max_megapixels: u64,
+    pub split_animation_frames: bool,
+    pub animation_frame_size: Option<u32>,
+    pub placeholders: PlaceholderMode,
+    pub aliases: BTreeMap<String, String>,
+    pub aliases_file: Option<String>,
+    pub export_filters: BTreeMap<String, String>,
+    pub export_filters_file: Option<String>,
+    pub version_check_url: Option<String>,
+    pub manifest_file: Option<String>,
+    pub phases: Vec<SyncPhase>,
+    pub export_extensions: Vec<String>,
+    pub export_unwrap: bool,
+    pub key_case: KeyCase,
+    pub emit_json_patch: bool,
+    pub emit_ndjson: bool,
+    pub store_compressed: Compression,
+    pub combined_exports: Vec<String>,
+    pub emit_image_meta: bool,
+    pub emit_inventory: bool,
+    pub emit_changelog: bool,
+    pub emit_names_list: bool,
+    pub emit_metrics: bool,
+    pub notice: Option<String>,
+    pub notice_file: Option<String>,
+    pub emit_delta_dir: bool,
+    pub min_index_entries: usize,
+    pub track_index_hash: bool,
+    pub track_index_order: bool,
+    pub hash_format: HashFormat,
+    pub verify_content_on_start: bool,
+    pub revalidate_unchanged: bool,
+    pub rebuild_hash_from_files: bool,
+    pub recover_corrupt_state: bool,
+    pub max_failures: usize,
+    pub min_free_bytes: Option<u64>,
+    pub force: bool,
+    pub flush_every: usize,
+    #[serde(
+        rename = "flush_interval_secs",
+        deserialize_with = "deserialize_interval"
+    )]
+    pub flush_interval: Duration,
+    pub batch_size: usize,
+    pub max_batches_per_run: usize,
+    pub keep_run_logs: usize,
+    pub schedule: Schedule,
+    pub retry_statuses: Vec<u16>,
+    pub tls_error: TlsErrorMode,
+    pub connect_retries: u32,
+    #[serde(rename = "spawn_delay_ms", deserialize_with = "deserialize_millis")]
+    pub spawn_delay: Duration,
+    #[serde(rename = "max_runtime_secs", deserialize_with = "deserialize_secs")]
+    pub max_runtime: Option<Duration>,
+    #[serde(rename = "loop_interval_secs", deserialize_with = "deserialize_secs")]
+    pub loop_interval: Option<Duration>,
+    #[cfg(feature = "http-cache")]
+    pub http_cache_dir: Option<String>,
+    #[cfg(feature = "http-cache")]
+    pub http_cache_mode: String,
+    #[cfg(feature = "serve")]
+    pub health_port: Option<u16>,
+    #[serde(skip)]
+    pub client: Option<ClientWithMiddleware>,
+    #[serde(skip)]
+    pub on_resource: Option<ResourceCallback>,
+    #[serde(skip)]
+    pub events: Option<UnboundedSender<SyncEvent>>,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        SyncConfig {
+            output_dir: "./output".to_string(),
+            export_dir: None,
+            image_dir: None,
+            origin_url: WARFRAME_ORIGIN_URL.to_string(),
+            content_url: WARFRAME_CONTENT_URL.to_string(),
+            export_url: None,
+            image_url: None,
+            extra_query: String::new(),
+            proxy_token: String::new(),
+            language: "en".to_string(),
+            languages: Vec::new(),
+            strip_metadata: false,
+            optimize_grayscale: false,
+            flatten_background: None,
+            output_format: OutputFormat::default(),
+            output_formats: Vec::new(),
+            size_formats: BTreeMap::new(),
+            size_dirs: BTreeMap::new(),
+            size_filters: BTreeMap::new(),
+            sharpen_amount: None,
+            size_sharpen: BTreeMap::new(),
+            dpi_sizes: BTreeMap::new(),
+            lazy_sizes: false,
+            only_sizes: Vec::new(),
+            prune_orphan_sizes: false,
+            max_concurrent_downloads: 0,
+            adaptive_concurrency: false,
+            adaptive_failure_rate: 0.2,
+            ramp_up: Duration::ZERO,
+            download_timeout: None,
+            max_concurrent_decode: thread::available_parallelism()
+                .map(|cpus| cpus.get())
+                .unwrap_or(1),
+            resize_parallelism: 2,
+            write_parallelism: 4,
+            allowed_image_formats: Vec::new(),
+            verify_outputs: false,
+            compute_phash: false,
+            store_dimensions: false,
+            max_megapixels: 64,
+            split_animation_frames: false,
+            animation_frame_size: None,
+            placeholders: PlaceholderMode::default(),
+            aliases: BTreeMap::new(),
+            aliases_file: None,
+            export_filters: BTreeMap::new(),
+            export_filters_file: None,
+            version_check_url: None,
+            manifest_file: None,
+            phases: vec![SyncPhase::Exports, SyncPhase::Images],
+            export_extensions: vec!["json".to_string()],
+            export_unwrap: false,
+            key_case: KeyCase::default(),
+            emit_json_patch: false,
+            emit_ndjson: false,
+            store_compressed: Compression::default(),
+            combined_exports: Vec::new(),
+            emit_image_meta: false,
+            emit_inventory: false,
+            emit_changelog: false,
+            emit_names_list: false,
+            emit_metrics: false,
+            notice: None,
+            notice_file: None,
+            emit_delta_dir: false,
+            min_index_entries: 0,
+            track_index_hash: false,
+            track_index_order: false,
+            hash_format: HashFormat::default(),
+            verify_content_on_start: false,
+            revalidate_unchanged: false,
+            rebuild_hash_from_files: false,
+            recover_corrupt_state: false,
+            max_failures: 0,
+            min_free_bytes: None,
+            force: false,
+            flush_every: 100,
+            flush_interval: Duration::from_secs(30),
+            batch_size: 0,
+            max_batches_per_run: 0,
+            keep_run_logs: 0,
+            schedule: Schedule::default(),
+            retry_statuses: vec![408, 429, 500, 502, 503, 504],
+            tls_error: TlsErrorMode::default(),
+            connect_retries: 4,
+            spawn_delay: Duration::ZERO,
+            max_runtime: None,
+            loop_interval: None,
+            #[cfg(feature = "http-cache")]
+            http_cache_dir: None,
+            #[cfg(feature = "http-cache")]
+            http_cache_mode: "default".to_string(),
+            #[cfg(feature = "serve")]
+            health_port: None,
+            client: None,
+            on_resource: None,
+            events: None,
+        }
+    }
+}
+
+impl SyncConfig {
+    /// Builds a configuration from environment variables, falling back to the defaults.
+    ///
+    /// # Returns
+    /// - A `SyncConfig` without a pre-built client, resource callback, or event channel.
+    pub fn from_env() -> Self {
+        SyncConfig::default().with_env()
+    }
+
+    /// Builds a configuration from a config file, then environment variables, then the defaults.
+    /// Environment variables take precedence over the config file, which takes precedence over the built-in defaults.
+    ///
+    /// # Arguments
+    /// - `config_file`: The path to a TOML or JSON config file; falls back to `CONFIG_FILE` if `None`.
+    ///
+    /// # Returns
+    /// - A `SyncConfig` without a pre-built client, resource callback, or event channel.
+    pub fn load(config_file: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let config_file = config_file
+            .map(|path| path.to_string())
+            .or_else(|| env::var("CONFIG_FILE").ok());
+
+        let base = match config_file {
+            Some(path) => SyncConfig::from_file(&path)?,
+            None => SyncConfig::default(),
+        };
+
+        Ok(base.with_env())
+    }
+
+    /// Reads a configuration from a TOML or JSON file, chosen by its extension.
+    ///
+    /// # Arguments
+    /// - `file_path`: The path to the config file.
+    ///
+    /// # Returns
+    /// - A `SyncConfig` with any fields missing from the file set to their defaults.
+    pub fn from_file(file_path: &str) -> Result<Self, Box<dyn Error>> {
+        let content = std::fs::read_to_string(file_path)
+            .map_err(|e| format!("Failed to read config file {}: {}", file_path, e))?;
+
+        let config = match Path::new(file_path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+        {
+            Some("json") => serde_json::from_str(&content)
+                .map_err(|e| format!("Invalid config file {}: {}", file_path, e))?,
+            _ => toml::from_str(&content)
+                .map_err(|e| format!("Invalid config file {}: {}", file_path, e))?,
+        };
+
+        Ok(config)
+    }
+
+    /// Overrides configuration values with any environment variables that are set.
+    ///
+    /// # Returns
+    /// - The `SyncConfig`, with environment variables applied over its current values.
+    pub fn with_env(self) -> Self {
+        let mut size_formats = self.size_formats;
+        size_formats.extend(env::vars().filter_map(|(key, value)| {
+            let size: u32 = key.strip_prefix("FORMAT_")?.parse().ok()?;
+            let format: OutputFormat = value.parse().ok()?;
+            Some((size, format))
+        }));
+
+        let mut size_sharpen = self.size_sharpen;
+        size_sharpen.extend(env::vars().filter_map(|(key, value)| {
+            let size: u32 = key.strip_prefix("SHARPEN_")?.parse().ok()?;
+            let amount: f32 = value.trim().parse().ok()?;
+            Some((size, amount))
+        }));
+
+        let mut size_filters = self.size_filters;
+        size_filters.extend(env::vars().filter_map(|(key, value)| {
+            let size: u32 = key.strip_prefix("FILTER_")?.parse().ok()?;
+            let filter: ResizeFilter = value.parse().ok()?;
+            Some((size, filter))
+        }));
+
+        SyncConfig {
+            output_dir: env::var("OUTPUT_DIRECTORY").unwrap_or(self.output_dir),
+            export_dir: env::var("EXPORT_DIRECTORY").ok().or(self.export_dir),
+            image_dir: env::var("IMAGE_DIRECTORY").ok().or(self.image_dir),
+            origin_url: env::var("WARFRAME_ORIGIN_URL").unwrap_or(self.origin_url),
+            content_url: env::var("WARFRAME_CONTENT_URL").unwrap_or(self.content_url),
+            export_url: env::var("WARFRAME_EXPORT_URL").ok().or(self.export_url),
+            image_url: env::var("WARFRAME_IMAGE_URL").ok().or(self.image_url),
+            extra_query: env::var("EXTRA_QUERY").unwrap_or(self.extra_query),
+            proxy_token: env::var("X_PROXY_TOKEN").unwrap_or(self.proxy_token),
+            language: env::var("LANGUAGE").unwrap_or(self.language),
+            languages: env_list("LANGUAGES").unwrap_or(self.languages),
+            strip_metadata: env_flag("STRIP_METADATA").unwrap_or(self.strip_metadata),
+            optimize_grayscale: env_flag("OPTIMIZE_GRAYSCALE").unwrap_or(self.optimize_grayscale),
+            flatten_background: env::var("FLATTEN_BACKGROUND")
+                .ok()
+                .and_then(|color| {
+                    let parsed = parse_hex_color(&color);
+                    if parsed.is_none() {
+                        println!("Ignoring invalid FLATTEN_BACKGROUND color: {}", color);
+                    }
+                    parsed
+                })
+                .or(self.flatten_background),
+            output_format: env_parse("OUTPUT_FORMAT").unwrap_or(self.output_format),
+            output_formats: env_list("OUTPUT_FORMATS")
+                .map(|formats| {
+                    let mut parsed = Vec::new();
+                    for format in formats.iter().filter_map(|format| format.parse().ok()) {
+                        if !parsed.contains(&format) {
+                            parsed.push(format);
+                        }
+                    }
+                    parsed
+                })
+                .unwrap_or(self.output_formats),
+            size_formats,
+            // Not `env_list`, which lowercases, since directory names are case-sensitive.
+            size_dirs: env::var("SIZE_DIRS")
+                .ok()
+                .map(|dirs| {
+                    dirs.split(',')
+                        .map(str::trim)
+                        .filter(|dir| !dir.is_empty())
+                        .filter_map(|dir| {
+                            let parsed = dir.split_once(':').and_then(|(size, name)| {
+                                Some((size.trim().parse().ok()?, name.trim().to_string()))
+                            });
+                            if parsed.is_none() {
+                                println!("Ignoring invalid SIZE_DIRS entry: {}", dir);
+                            }
+                            parsed
+                        })
+                        .collect()
+                })
+                .unwrap_or(self.size_dirs),
+            size_filters,
+            sharpen_amount: env_parse("SHARPEN_AMOUNT").or(self.sharpen_amount),
+            size_sharpen,
+            dpi_sizes: env_list("DPI_SIZES")
+                .map(|entries| {
+                    entries
+                        .iter()
+                        .filter_map(|entry| {
+                            let (density, size) = entry.split_once('=')?;
+                            Some((density.parse().ok()?, size.parse().ok()?))
+                        })
+                        .collect()
+                })
+                .unwrap_or(self.dpi_sizes),
+            lazy_sizes: env_flag("LAZY_SIZES").unwrap_or(self.lazy_sizes),
+            only_sizes: env_list("ONLY_SIZES")
+                .map(|sizes| sizes.iter().filter_map(|size| size.parse().ok()).collect())
+                .unwrap_or(self.only_sizes),
+            prune_orphan_sizes: env_flag("PRUNE_ORPHAN_SIZES").unwrap_or(self.prune_orphan_sizes),
+            max_concurrent_downloads: env_parse("MAX_CONCURRENT_DOWNLOADS")
+                .unwrap_or(self.max_concurrent_downloads),
+            adaptive_concurrency: env_flag("ADAPTIVE_CONCURRENCY")
+                .unwrap_or(self.adaptive_concurrency),
+            adaptive_failure_rate: env_parse("ADAPTIVE_FAILURE_RATE")
+                .unwrap_or(self.adaptive_failure_rate),
+            ramp_up: env_parse("RAMP_UP_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(self.ramp_up),
+            download_timeout: env_parse("DOWNLOAD_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .or(self.download_timeout),
+            max_concurrent_decode: env_parse("MAX_CONCURRENT_DECODE")
+                .unwrap_or(self.max_concurrent_decode),
+            resize_parallelism: env_parse("RESIZE_PARALLELISM").unwrap_or(self.resize_parallelism),
+            write_parallelism: env_parse("WRITE_PARALLELISM").unwrap_or(self.write_parallelism),
+            allowed_image_formats: env_list("ALLOWED_IMAGE_FORMATS")
+                .unwrap_or(self.allowed_image_formats),
+            verify_outputs: env_flag("VERIFY_OUTPUTS").unwrap_or(self.verify_outputs),
+            compute_phash: env_flag("COMPUTE_PHASH").unwrap_or(self.compute_phash),
+            store_dimensions: env_flag("STORE_DIMENSIONS").unwrap_or(self.store_dimensions),
+            max_megapixels: env_parse("MAX_MEGAPIXELS").unwrap_or(self.max_megapixels),
+            split_animation_frames: env_flag("SPLIT_ANIMATION_FRAMES")
+                .unwrap_or(self.split_animation_frames),
+            animation_frame_size: env_parse("ANIMATION_FRAME_SIZE").or(self.animation_frame_size),
+            placeholders: env_parse("PLACEHOLDERS").unwrap_or(self.placeholders),
+            aliases: self.aliases,
+            aliases_file: env::var("ALIASES_FILE").ok().or(self.aliases_file),
+            export_filters: self.export_filters,
+            export_filters_file: env::var("EXPORT_FILTERS_FILE")
+                .ok()
+                .or(self.export_filters_file),
+            version_check_url: env::var("VERSION_CHECK_URL")
+                .ok()
+                .or(self.version_check_url),
+            manifest_file: env::var("MANIFEST_FILE").ok().or(self.manifest_file),
+            phases: env_list("PHASES")
+                .map(|phases| {
+                    phases
+                        .iter()
+                        .filter_map(|phase| phase.parse().ok())
+                        .collect()
+                })
+                .unwrap_or(self.phases),
+            export_extensions: env_list("EXPORT_EXTENSIONS").unwrap_or(self.export_extensions),
+            export_unwrap: env_flag("EXPORT_UNWRAP").unwrap_or(self.export_unwrap),
+            key_case: env_parse("KEY_CASE").unwrap_or(self.key_case),
+            emit_json_patch: env_flag("EMIT_JSON_PATCH").unwrap_or(self.emit_json_patch),
+            emit_ndjson: env_flag("EMIT_NDJSON").unwrap_or(self.emit_ndjson),
+            store_compressed: env_parse("STORE_COMPRESSED").unwrap_or(self.store_compressed),
+            combined_exports: env_list("COMBINED_EXPORTS").unwrap_or(self.combined_exports),
+            emit_image_meta: env_flag("EMIT_IMAGE_META").unwrap_or(self.emit_image_meta),
+            emit_inventory: env_flag("EMIT_INVENTORY").unwrap_or(self.emit_inventory),
+            emit_changelog: env_flag("EMIT_CHANGELOG").unwrap_or(self.emit_changelog),
+            emit_names_list: env_flag("EMIT_NAMES_LIST").unwrap_or(self.emit_names_list),
+            emit_metrics: env_flag("EMIT_METRICS").unwrap_or(self.emit_metrics),
+            notice: env::var("NOTICE").ok().or(self.notice),
+            notice_file: env::var("NOTICE_FILE").ok().or(self.notice_file),
+            emit_delta_dir: env_flag("EMIT_DELTA_DIR").unwrap_or(self.emit_delta_dir),
+            min_index_entries: env_parse("MIN_INDEX_ENTRIES").unwrap_or(self.min_index_entries),
+            track_index_hash: env_flag("TRACK_INDEX_HASH").unwrap_or(self.track_index_hash),
+            track_index_order: env_flag("TRACK_INDEX_ORDER").unwrap_or(self.track_index_order),
+            hash_format: env_parse("HASH_FORMAT").unwrap_or(self.hash_format),
+            verify_content_on_start: env_flag("VERIFY_CONTENT_ON_START")
+                .unwrap_or(self.verify_content_on_start),
+            revalidate_unchanged: env_flag("REVALIDATE_UNCHANGED")
+                .unwrap_or(self.revalidate_unchanged),
+            rebuild_hash_from_files: env_flag("REBUILD_HASH_FROM_FILES")
+                .unwrap_or(self.rebuild_hash_from_files),
+            recover_corrupt_state: env_flag("RECOVER_CORRUPT_STATE")
+                .unwrap_or(self.recover_corrupt_state),
+            max_failures: env_parse("MAX_FAILURES").unwrap_or(self.max_failures),
+            min_free_bytes: env_parse("MIN_FREE_BYTES").or(self.min_free_bytes),
+            force: env_flag("FORCE").unwrap_or(self.force),
+            flush_every: env_parse("FLUSH_EVERY_N").unwrap_or(self.flush_every),
+            flush_interval: env_parse("FLUSH_INTERVAL_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(self.flush_interval),
+            batch_size: env_parse("BATCH_SIZE").unwrap_or(self.batch_size),
+            max_batches_per_run: env_parse("MAX_BATCHES_PER_RUN")
+                .unwrap_or(self.max_batches_per_run),
+            keep_run_logs: env_parse("KEEP_RUN_LOGS").unwrap_or(self.keep_run_logs),
+            schedule: env_parse("SCHEDULE").unwrap_or(self.schedule),
+            retry_statuses: env_list("RETRY_STATUSES")
+                .map(|statuses| {
+                    statuses
+                        .iter()
+                        .filter_map(|status| status.parse().ok())
+                        .collect()
+                })
+                .unwrap_or(self.retry_statuses),
+            tls_error: env_parse("TLS_ERROR").unwrap_or(self.tls_error),
+            connect_retries: env_parse("CONNECT_RETRIES").unwrap_or(self.connect_retries),
+            spawn_delay: env_parse("SPAWN_DELAY_MS")
+                .map(Duration::from_millis)
+                .unwrap_or(self.spawn_delay),
+            max_runtime: env_parse("MAX_RUNTIME_SECS")
+                .map(Duration::from_secs)
+                .or(self.max_runtime),
+            loop_interval: env_parse("LOOP_INTERVAL_SECS")
+                .map(Duration::from_secs)
+                .or(self.loop_interval),
+            #[cfg(feature = "http-cache")]
+            http_cache_dir: env::var("HTTP_CACHE_DIR").ok().or(self.http_cache_dir),
+            #[cfg(feature = "http-cache")]
+            http_cache_mode: env::var("HTTP_CACHE_MODE").unwrap_or(self.http_cache_mode),
+            #[cfg(feature = "serve")]
+            health_port: env_parse("HEALTH_PORT").or(self.health_port),
+            client: self.client,
+            on_resource: self.on_resource,
+            events: self.events,
+        }
+    }
+
+    /// The configuration for mirroring one of `languages`, into its own subdirectory.
+    pub fn for_language(&self, language: &str) -> SyncConfig {
+        let mut config = self.clone();
+        config.language = language.to_string();
+        config.languages = Vec::new();
+        config.output_dir = format!("{}/{}", self.output_dir, language);
+        config.export_dir = self
+            .export_dir
+            .as_ref()
+            .map(|dir| format!("{}/{}", dir, language));
+        config.image_dir = self
+            .image_dir
+            .as_ref()
+            .map(|dir| format!("{}/{}", dir, language));
+        config
+    }
+
+    /// The location of a hash map, e.g. `export` for `{output_dir}/export_hash.json`.
+    pub fn hash_location(&self, name: &str) -> String {
+        self.hash_location_as(name, self.hash_format)
+    }
+
+    /// The location a hash map would have in the given format.
+    pub fn hash_location_as(&self, name: &str, format: HashFormat) -> String {
+        format!("{}/{}_hash.{}", self.output_dir, name, format.extension())
+    }
+
+    /// The location of the ETags stored alongside a hash map, e.g. `export` for `{output_dir}/export_etag.json`.
+    pub fn etag_location(&self, name: &str) -> String {
+        format!(
+            "{}/{}_etag.{}",
+            self.output_dir,
+            name,
+            self.hash_format.extension()
+        )
+    }
+
+    /// The primary encoding for image outputs of the given size, used when only one can be served.
+    pub fn format_for(&self, size: u32) -> OutputFormat {
+        self.formats_for(size)[0]
+    }
+
+    /// Every encoding image outputs of the given size are written in, starting with the primary one.
+    pub fn formats_for(&self, size: u32) -> Vec<OutputFormat> {
+        match self.size_formats.get(&size) {
+            Some(format) => vec![*format],
+            None if !self.output_formats.is_empty() => self.output_formats.clone(),
+            None => vec![self.output_format],
+        }
+    }
+
+    /// Whether image outputs of the given size are written on this run, as limited by `only_sizes`.
+    pub fn writes_size(&self, size: u32) -> bool {
+        self.only_sizes.is_empty() || self.only_sizes.contains(&size)
+    }
+
+    /// Whether downloads are ramped up to `max_concurrent_downloads`, rather than starting at it.
+    pub fn ramps_up(&self) -> bool {
+        !self.ramp_up.is_zero() && self.max_concurrent_downloads > 1
+    }
+
+    /// Whether a run goes through the given phase, as limited by `phases`.
+    pub fn runs_phase(&self, phase: SyncPhase) -> bool {
+        self.phases.contains(&phase)
+    }
+
+    /// Whether source images of the given format should be processed.
+    /// Formats are matched by any of their file extensions, so `jpg` and `jpeg` are equivalent.
+    pub fn allows_image_format(&self, format: ImageFormat) -> bool {
+        self.allowed_image_formats.is_empty()
+            || format.extensions_str().iter().any(|extension| {
+                self.allowed_image_formats
+                    .iter()
+                    .any(|allowed| allowed == extension)
+            })
+    }
+
+    /// The server exports are fetched from.
+    pub fn export_url(&self) -> &str {
+        self.export_url.as_deref().unwrap_or(&self.content_url)
+    }
+
+    /// The server images are fetched from.
+    pub fn image_url(&self) -> &str {
+        self.image_url.as_deref().unwrap_or(&self.content_url)
+    }
+
+    /// Parses a request URL, merging the `extra_query` parameters into any existing query string.
+    pub fn request_url(&self, url: &str) -> Result<Url, Box<dyn Error>> {
+        let mut url = Url::parse(url)?;
+        if !self.extra_query.is_empty() {
+            url.query_pairs_mut()
+                .extend_pairs(form_urlencoded::parse(self.extra_query.as_bytes()));
+        }
+        Ok(url)
+    }
+
+    /// The formats every stored image size is written in, keyed by size (`512` is the original).
+    /// Resized variants are only included when they aren't generated lazily.
+    pub fn image_formats(&self) -> BTreeMap<u32, Vec<OutputFormat>> {
+        let sizes = if self.lazy_sizes { &[] } else { IMAGE_SIZES };
+        std::iter::once(&512)
+            .chain(sizes)
+            .map(|size| (*size, self.formats_for(*size)))
+            .collect()
+    }
+
+    /// The filter for image outputs of the given size.
+    /// Sizes up to `AREA_FILTER_MAX_SIZE` use area averaging by default, as Lanczos3 rings on small icons.
+    pub fn filter_for(&self, size: u32) -> ResizeFilter {
+        self.size_filters
+            .get(&size)
+            .copied()
+            .unwrap_or(if size <= AREA_FILTER_MAX_SIZE {
+                ResizeFilter::Area
+            } else {
+                ResizeFilter::Lanczos3
+            })
+    }
+
+    /// The jq filter for an export, if one is configured for its kind.
+    /// Kinds are matched case-insensitively, e.g. `weapons` applies to `ExportWeapons_en`.
+    pub fn export_filter(&self, name: &str) -> Option<&str> {
+        let kind = resource_kind(name);
+        self.export_filters
+            .iter()
+            .find(|(filter_kind, _)| filter_kind.eq_ignore_ascii_case(&kind))
+            .map(|(_, filter)| filter.as_str())
+    }
+
+    /// The name of the directory resized outputs of the given size are written to, e.g. `64x64`.
+    pub fn size_dir(&self, size: u32) -> String {
+        self.size_dirs
+            .get(&size)
+            .cloned()
+            .unwrap_or_else(|| format!("{}x{}", size, size))
+    }
+
+    /// Checks that every `size_dirs` name is a single, distinct directory name inside the image directory.
+    ///
+    /// # Returns
+    /// - `Ok(())` if the names are valid, or an error naming the first invalid one.
+    pub fn check_size_dirs(&self) -> Result<(), Box<dyn Error>> {
+        for (size, name) in &self.size_dirs {
+            if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+                return Err(format!("Invalid SIZE_DIRS directory for {}: {:?}", size, name).into());
+            }
+        }
+        let mut seen = Vec::new();
+        for size in IMAGE_SIZES {
+            let name = self.size_dir(*size);
+            if seen.contains(&name) {
+                return Err(format!("Duplicate SIZE_DIRS directory for {}: {}", size, name).into());
+            }
+            seen.push(name);
+        }
+        Ok(())
+    }
+
+    /// The sigma of the unsharp mask for image outputs of the given size, if they're sharpened.
+    /// `sharpen_amount` only applies to resized variants, so originals stay as provided unless overridden.
+    pub fn sharpen_for(&self, size: u32) -> Option<f32> {
+        match self.size_sharpen.get(&size) {
+            Some(amount) => Some(*amount),
+            None if size != 512 => self.sharpen_amount,
+            None => None,
+        }
+        .filter(|amount| *amount > 0.0)
+    }
+
+    /// The directory where the sanitized source of each export is cached.
+    pub fn source_dir(&self) -> String {
+        format!("{}/source", self.output_dir)
+    }
+
+    /// A fingerprint of the options that affect how exports are rendered.
+    /// When it changes, exports are regenerated from their cached source.
+    pub fn export_fingerprint(&self) -> String {
+        let mut fingerprint = format!("ndjson={}", self.emit_ndjson);
+        // Only appended when set, so existing fingerprints stay valid.
+        if self.key_case != KeyCase::Verbatim {
+            fingerprint.push_str(&format!(";key_case={}", self.key_case));
+        }
+        if self.export_unwrap {
+            fingerprint.push_str(";unwrap=true");
+        }
+        if self.store_compressed != Compression::None {
+            fingerprint.push_str(&format!(";compressed={}", self.store_compressed));
+        }
+        // Hashed, as filters can be long; the map is sorted by kind, so the hash is stable.
+        if !self.export_filters.is_empty() {
+            let filters: String = self
+                .export_filters
+                .iter()
+                .map(|(kind, filter)| format!("{}\0{}\0", kind, filter))
+                .collect();
+            let hash = blake3::hash(filters.as_bytes()).to_hex();
+            fingerprint.push_str(&format!(";filters={}", &hash[..16]));
+        }
+        fingerprint
+    }
+
+    /// The directory where the outputs written by the run started at `started_at` are copied to.
+    pub fn delta_dir(&self, started_at: u64) -> String {
+        format!("{}/delta/{}", self.output_dir, started_at)
+    }
+
+    /// The directory where exports are stored.
+    pub fn export_dir(&self) -> String {
+        self.export_dir
+            .clone()
+            .unwrap_or_else(|| format!("{}/export", self.output_dir))
+    }
+
+    /// The directory where images and their resized variants are stored.
+    pub fn image_dir(&self) -> String {
+        self.image_dir
+            .clone()
+            .unwrap_or_else(|| format!("{}/image", self.output_dir))
+    }
+}
+
+/// The largest output size that is downscaled with area averaging, unless overridden.
+const AREA_FILTER_MAX_SIZE: u32 = 32;
+
+/// Reads a boolean flag from an environment variable.
+///
+/// # Arguments
+/// - `key`: The name of the environment variable.
+///
+/// # Returns
+/// - `Some(true)` for `true`/`1`, `Some(false)` for any other value, or `None` if unset.
+fn env_flag(key: &str) -> Option<bool> {
+    env::var(key)
+        .ok()
+        .map(|value| matches!(value.to_lowercase().as_str(), "true" | "1"))
+}
+
+/// Parses a value from an environment variable.
+///
+/// # Arguments
+/// - `key`: The name of the environment variable.
+///
+/// # Returns
+/// - `Some(value)` if the variable is set and parses, or `None` otherwise.
+fn env_parse<T: FromStr>(key: &str) -> Option<T> {
+    env::var(key)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+}
+
+/// Reads a comma-separated list from an environment variable.
+///
+/// # Arguments
+/// - `key`: The name of the environment variable.
+///
+/// # Returns
+/// - `Some(items)` with each item trimmed and lowercased, or `None` if unset.
+fn env_list(key: &str) -> Option<Vec<String>> {
+    env::var(key).ok().map(|value| {
+        value
+            .split(',')
+            .map(|item| item.trim().to_lowercase())
+            .filter(|item| !item.is_empty())
+            .collect()
+    })
+}
+
+/// Deserializes an optional `#RRGGBB` color string.
+fn deserialize_hex_color<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<[u8; 3]>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(color) => parse_hex_color(&color)
+            .map(Some)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid color: {}", color))),
+        None => Ok(None),
+    }
+}
+
+/// Deserializes an optional duration given in whole seconds.
+fn deserialize_secs<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_secs))
+}
+
+/// Deserializes a duration given in whole seconds.
+fn deserialize_interval<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    Ok(Duration::from_secs(u64::deserialize(deserializer)?))
+}
+
+/// Deserializes a duration given in whole milliseconds.
+fn deserialize_millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    Ok(Duration::from_millis(u64::deserialize(deserializer)?))
+}
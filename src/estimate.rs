@@ -0,0 +1,66 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::sync::{build_client, download_export_manifest};
+use crate::{SyncConfig, IMAGE_SIZES};
+
+/// A rough average of how many bytes an encoded image takes per pixel.
+/// Icons are mostly flat colors with transparency, which compress well.
+const ESTIMATED_BYTES_PER_PIXEL: f64 = 0.8;
+
+/// Struct that holds an estimate of how much a full image mirror would store.
+/// - `images`: The number of images in the export manifest.
+/// - `files`: The number of image files that would be written, including resized variants.
+/// - `bytes`: A rough estimate of the total size of those files.
+#[derive(Debug, Default)]
+pub struct Estimate {
+    pub images: usize,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl fmt::Display for Estimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} images, {} files, ~{:.1} MiB",
+            self.images,
+            self.files,
+            self.bytes as f64 / (1024.0 * 1024.0)
+        )
+    }
+}
+
+/// Estimates the storage a full image mirror needs, without downloading any images.
+/// Only the export index and the export manifest are fetched.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the server URLs and image options.
+///
+/// # Returns
+/// - An `Estimate` of the number of image files and their total size.
+pub async fn estimate(config: &SyncConfig) -> Result<Estimate, Box<dyn Error>> {
+    let client = match &config.client {
+        Some(client) => client.clone(),
+        None => build_client(config)?,
+    };
+
+    let manifest = download_export_manifest(&client, config).await?;
+
+    // The original is always stored; resized variants only when they aren't generated lazily.
+    let mut sizes = vec![512];
+    if !config.lazy_sizes {
+        sizes.extend_from_slice(IMAGE_SIZES);
+    }
+    let bytes_per_image: f64 = sizes
+        .iter()
+        .map(|size| (size * size) as f64 * ESTIMATED_BYTES_PER_PIXEL)
+        .sum();
+
+    let images = manifest.Manifest.len();
+    Ok(Estimate {
+        images,
+        files: images * sizes.len(),
+        bytes: (images as f64 * bytes_per_image) as u64,
+    })
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A phase of a sync run.
+/// - `Exports`: Changed exports are being downloaded.
+/// - `Images`: Changed images from the export manifest are being downloaded.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncPhase {
+    #[serde(alias = "export")]
+    Exports,
+    #[serde(alias = "image")]
+    Images,
+}
+
+impl FromStr for SyncPhase {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "export" | "exports" => Ok(SyncPhase::Exports),
+            "image" | "images" => Ok(SyncPhase::Images),
+            other => Err(format!("Unknown phase: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for SyncPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncPhase::Exports => write!(f, "exports"),
+            SyncPhase::Images => write!(f, "images"),
+        }
+    }
+}
+
+/// A progress event of a sync run, sent over `SyncConfig::events`.
+/// - `Started`: A download of the named resource was started.
+/// - `Completed`: The named resource was downloaded and processed, with the size of its content in bytes.
+/// - `Failed`: The named resource failed to download or process, with the error.
+/// - `PhaseChanged`: The run moved on to the given phase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncEvent {
+    Started(String),
+    Completed(String, u64),
+    Failed(String, String),
+    PhaseChanged(SyncPhase),
+}
@@ -0,0 +1,149 @@
+use image::ImageReader;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+
+use crate::sync::{build_client, download_export_manifest, download_image};
+use crate::{
+    load_hash_map_from_file, split_string_to_resource, ImageHash, OutputSink, SinkFuture,
+    SkipReason, SyncConfig,
+};
+
+/// Struct that holds what processing a single image would do, as reported by `inspect`.
+/// - `unique_name`: The unique name of the image in the export manifest.
+/// - `texture_location`: The texture location listed for it in the export manifest.
+/// - `manifest_hash`: The hash of the image in the export manifest.
+/// - `stored_hash`: The manifest hash stored in the image hash map, if the image was processed before.
+/// - `bytes`: The size of the downloaded texture.
+/// - `source_format`: The format of the downloaded texture, if it could be determined.
+/// - `dimensions`: The width and height of the downloaded texture, if it could be decoded.
+/// - `skipped`: Why the image would not be saved, if it wouldn't.
+/// - `outputs`: The paths the outputs would be written to, with their sizes.
+#[derive(Debug, Default)]
+pub struct Inspection {
+    pub unique_name: String,
+    pub texture_location: String,
+    pub manifest_hash: String,
+    pub stored_hash: Option<String>,
+    pub bytes: u64,
+    pub source_format: Option<String>,
+    pub dimensions: Option<(u32, u32)>,
+    pub skipped: Option<SkipReason>,
+    pub outputs: Vec<(String, usize)>,
+}
+
+impl fmt::Display for Inspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Unique name: {}", self.unique_name)?;
+        writeln!(f, "Texture: {}", self.texture_location)?;
+        writeln!(f, "Manifest hash: {}", self.manifest_hash)?;
+        writeln!(
+            f,
+            "Stored hash: {}",
+            self.stored_hash.as_deref().unwrap_or("none")
+        )?;
+        writeln!(f, "Downloaded: {} bytes", self.bytes)?;
+        writeln!(
+            f,
+            "Source format: {}",
+            self.source_format.as_deref().unwrap_or("unknown")
+        )?;
+        match self.dimensions {
+            Some((width, height)) => writeln!(f, "Dimensions: {}x{}", width, height)?,
+            None => writeln!(f, "Dimensions: unknown")?,
+        }
+        if let Some(reason) = self.skipped {
+            writeln!(f, "Skipped: {}", reason)?;
+        }
+        write!(f, "Outputs: {}", self.outputs.len())?;
+        for (path, size) in &self.outputs {
+            write!(f, "\n  {} ({} bytes)", path, size)?;
+        }
+        Ok(())
+    }
+}
+
+/// A sink that keeps the paths and sizes of outputs instead of writing them.
+#[derive(Default)]
+struct RecordingSink {
+    outputs: Mutex<Vec<(String, usize)>>,
+}
+
+impl OutputSink for RecordingSink {
+    fn write<'a>(&'a self, path: &'a str, bytes: &'a [u8]) -> SinkFuture<'a> {
+        self.outputs
+            .lock()
+            .unwrap()
+            .push((path.to_string(), bytes.len()));
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Processes a single image end-to-end, as a sync would, without writing anything to disk.
+/// The export index and manifest are fetched to find the image, which is then downloaded, decoded and
+/// resized with the configured options; its outputs are only recorded.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the server URLs and image options.
+/// - `unique_name`: The unique name of the image, e.g. `/Lotus/Interface/Icons/Foo`.
+///
+/// # Returns
+/// - An `Inspection` of the image and the outputs it would have.
+pub async fn inspect(config: &SyncConfig, unique_name: &str) -> Result<Inspection, Box<dyn Error>> {
+    let client = match &config.client {
+        Some(client) => client.clone(),
+        None => build_client(config)?,
+    };
+
+    let manifest = download_export_manifest(&client, config).await?;
+    let item = manifest
+        .Manifest
+        .into_iter()
+        .find(|item| item.unique_name == unique_name)
+        .ok_or_else(|| format!("{} is not in the export manifest", unique_name))?;
+    let manifest_hash = split_string_to_resource(&item.texture_location)?.hash;
+
+    let stored_hashes: BTreeMap<String, ImageHash> =
+        load_hash_map_from_file(&config.hash_location("image")).await?;
+    let stored_hash = stored_hashes
+        .get(unique_name)
+        .map(|hash| hash.manifest_hash.clone());
+
+    // The downloaded texture is passed to the callback, so it can be described before it's resized.
+    let texture = Arc::new(Mutex::new(None));
+    let mut config = config.clone();
+    config.verify_outputs = false;
+    config.on_resource = Some({
+        let texture = Arc::clone(&texture);
+        Arc::new(move |_, content: &[u8]| *texture.lock().unwrap() = Some(content.to_vec()))
+    });
+
+    let sink = Arc::new(RecordingSink::default());
+    let (bytes, skipped) = download_image(
+        client,
+        config,
+        Arc::clone(&sink) as Arc<dyn OutputSink>,
+        &item,
+    )
+    .await?;
+
+    let mut inspection = Inspection {
+        unique_name: item.unique_name,
+        texture_location: item.texture_location,
+        manifest_hash,
+        stored_hash,
+        bytes,
+        skipped,
+        outputs: sink.outputs.lock().unwrap().clone(),
+        ..Inspection::default()
+    };
+    if let Some(content) = texture.lock().unwrap().take() {
+        let reader = ImageReader::new(Cursor::new(content)).with_guessed_format()?;
+        inspection.source_format = reader.format().map(|format| format!("{:?}", format));
+        inspection.dimensions = reader.into_dimensions().ok();
+    }
+
+    Ok(inspection)
+}
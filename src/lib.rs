@@ -1,26 +1,57 @@
 use fast_image_resize::images::Image;
-use fast_image_resize::{PixelType, ResizeOptions, Resizer};
+use fast_image_resize::{FilterType, PixelType, ResizeAlg, ResizeOptions, Resizer};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
 use image::codecs::png::PngEncoder;
-use image::ImageEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{imageops, DynamicImage, GrayAlphaImage, GrayImage, ImageEncoder, RgbImage, RgbaImage};
+use jaq_core::load::{Arena, File, Loader};
+use jaq_core::{data, unwrap_valr, Compiler, Ctx, Vars};
 use regex::{Captures, Regex};
-use serde::Deserialize;
-use std::collections::BTreeMap;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 use std::error::Error;
-use std::io::BufWriter;
+use std::fmt;
+use std::io::{self, BufWriter, Read, Write};
 use std::path::Path;
+use std::str::FromStr;
 use std::sync::LazyLock;
 use tokio::fs;
 
-pub static WARFRAME_ORIGIN_URL: &'static str = "https://origin.warframe.com";
-pub static WARFRAME_CONTENT_URL: &'static str = "https://content.warframe.com";
-pub static LZMA_URL_PATH: &'static str = "/PublicExport/index_en.txt.lzma";
-pub static MANIFEST_PATH: &'static str = "/PublicExport/Manifest";
-pub static PUBLIC_EXPORT_PATH: &'static str = "/PublicExport";
+mod config;
+mod estimate;
+mod event;
+mod inspect;
+mod log;
+mod report;
+#[cfg(feature = "serve")]
+mod serve;
+mod sink;
+mod sync;
+pub mod urls;
+
+pub use config::{ResourceCallback, Schedule, SyncConfig};
+pub use estimate::{estimate, Estimate};
+pub use event::{SyncEvent, SyncPhase};
+pub use inspect::{inspect, Inspection};
+pub use report::{
+    FailedResource, RedirectedResource, RunReport, SkipReason, SkippedResource, SyncOutcome,
+};
+#[cfg(feature = "serve")]
+pub use serve::{serve, serve_health, HealthStatus};
+pub use sink::{FileSystemSink, OutputSink, SinkFuture};
+pub use sync::{run_sync, DiskFull};
+
+pub static WARFRAME_ORIGIN_URL: &str = "https://origin.warframe.com";
+pub static WARFRAME_CONTENT_URL: &str = "https://content.warframe.com";
+pub static LZMA_URL_PATH: &str = "/PublicExport/index_{language}.txt.lzma";
+pub static MANIFEST_PATH: &str = "/PublicExport/Manifest";
+pub static PUBLIC_EXPORT_PATH: &str = "/PublicExport";
 
 pub const IMAGE_SIZES: &[u32] = &[256, 128, 64, 32];
 
 pub static RE_ESCAPES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[\r\n]").unwrap());
-pub static UNWRAP_NONE: LazyLock<String> = LazyLock::new(|| String::from("None"));
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -55,6 +86,482 @@ pub struct Resource {
     pub hash: String,
 }
 
+/// The encoding used for image outputs.
+/// - `Png`: Lossless PNG.
+/// - `WebP`: Lossless WebP.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    WebP,
+}
+
+impl OutputFormat {
+    /// The file extension for this format, without the leading `.`.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::WebP => "webp",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "png" => Ok(OutputFormat::Png),
+            "webp" => Ok(OutputFormat::WebP),
+            other => Err(format!("Unknown output format: {}", other)),
+        }
+    }
+}
+
+/// The encoding used for the hash maps.
+/// - `Json`: Human-readable JSON, e.g. `image_hash.json`.
+/// - `MsgPack`: Compact MessagePack, e.g. `image_hash.msgpack`, for clients that can't parse JSON cheaply.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HashFormat {
+    #[default]
+    Json,
+    MsgPack,
+}
+
+impl HashFormat {
+    /// The file extension for this format, without the leading `.`.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            HashFormat::Json => "json",
+            HashFormat::MsgPack => "msgpack",
+        }
+    }
+}
+
+impl FromStr for HashFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "json" => Ok(HashFormat::Json),
+            "msgpack" => Ok(HashFormat::MsgPack),
+            other => Err(format!("Unknown hash format: {}", other)),
+        }
+    }
+}
+
+/// The compression exports are stored with.
+/// - `None`: Plain files, e.g. `ExportWeapons_en.json`.
+/// - `Gzip`: Gzip-compressed files, e.g. `ExportWeapons_en.json.gz`.
+/// - `Zstd`: Zstandard-compressed files, e.g. `ExportWeapons_en.json.zst`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The suffix appended to the file name of compressed outputs, including the leading `.`.
+    pub fn suffix(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+        }
+    }
+
+    /// Compresses the content of an output.
+    pub fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(bytes.to_vec()),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+            Compression::Zstd => zstd::encode_all(bytes, 0),
+        }
+    }
+
+    /// Decompresses the content of an output written with this compression.
+    pub fn decompress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(bytes.to_vec()),
+            Compression::Gzip => {
+                let mut decompressed = Vec::new();
+                GzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+            Compression::Zstd => zstd::decode_all(bytes),
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            other => Err(format!("Unknown compression: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Compression::None => write!(f, "none"),
+            Compression::Gzip => write!(f, "gzip"),
+            Compression::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/// How placeholder images, which are at most 1 pixel wide or high, or fully transparent, are handled.
+/// - `Keep`: Processed like any other image.
+/// - `Skip`: Not written, and recorded as skipped until the image changes.
+/// - `Marker`: Not written, but marked with an empty `{name}.placeholder` file in the image directory.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaceholderMode {
+    #[default]
+    Keep,
+    Skip,
+    Marker,
+}
+
+impl FromStr for PlaceholderMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "keep" => Ok(PlaceholderMode::Keep),
+            "skip" => Ok(PlaceholderMode::Skip),
+            "marker" => Ok(PlaceholderMode::Marker),
+            other => Err(format!("Unknown placeholder mode: {}", other)),
+        }
+    }
+}
+
+/// How TLS and certificate errors reaching the content servers are handled.
+/// - `Retry`: Retried like any other network error, e.g. for a flaky intercepting proxy.
+/// - `Abort`: Fail immediately, as a misconfigured certificate won't fix itself.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsErrorMode {
+    #[default]
+    Retry,
+    Abort,
+}
+
+impl FromStr for TlsErrorMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "retry" => Ok(TlsErrorMode::Retry),
+            "abort" => Ok(TlsErrorMode::Abort),
+            other => Err(format!("Unknown TLS error mode: {}", other)),
+        }
+    }
+}
+
+/// The filter used when downscaling images.
+/// - `Lanczos3`: Sharp, but prone to ringing around high-contrast edges at small sizes.
+/// - `Area`: Averages every source pixel covered by an output pixel; softer, but without ringing.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeFilter {
+    Lanczos3,
+    Area,
+}
+
+impl ResizeFilter {
+    /// The `fast_image_resize` algorithm for this filter.
+    fn resize_alg(&self) -> ResizeAlg {
+        match self {
+            ResizeFilter::Lanczos3 => ResizeAlg::Interpolation(FilterType::Lanczos3),
+            ResizeFilter::Area => ResizeAlg::Convolution(FilterType::Box),
+        }
+    }
+}
+
+impl FromStr for ResizeFilter {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "lanczos3" => Ok(ResizeFilter::Lanczos3),
+            "area" => Ok(ResizeFilter::Area),
+            other => Err(format!("Unknown resize filter: {}", other)),
+        }
+    }
+}
+
+/// The naming convention export JSON keys are rewritten to.
+/// - `Verbatim`: Keys are kept as provided by the content server.
+/// - `CamelCase`: Keys are rewritten as `camelCase`.
+/// - `SnakeCase`: Keys are rewritten as `snake_case`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyCase {
+    #[default]
+    Verbatim,
+    CamelCase,
+    SnakeCase,
+}
+
+impl FromStr for KeyCase {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "none" | "verbatim" => Ok(KeyCase::Verbatim),
+            "camelcase" | "camel_case" => Ok(KeyCase::CamelCase),
+            "snakecase" | "snake_case" => Ok(KeyCase::SnakeCase),
+            other => Err(format!("Unknown key case: {}", other)),
+        }
+    }
+}
+
+impl fmt::Display for KeyCase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyCase::Verbatim => write!(f, "verbatim"),
+            KeyCase::CamelCase => write!(f, "camel_case"),
+            KeyCase::SnakeCase => write!(f, "snake_case"),
+        }
+    }
+}
+
+/// Struct that holds state carried between runs, persisted as `version.json`.
+/// - `index_etag`: The ETag of the last export index that was fully processed, if provided by the server.
+/// - `export_fingerprint`: The rendering options the export outputs were last written with.
+/// - `image_cursor`: The position in the manifest to resume the image phase from, if it didn't finish.
+///   Every item before it was processed; saved at batch boundaries and while images are downloaded.
+/// - `last_version`: The upstream version reported by `version_check_url` when the last run fully succeeded.
+/// - `index_hash`: The BLAKE3 hash of the last decompressed export index, if `track_index_hash` is enabled.
+/// - `index_order`: The resource names of the last export index, in order, if `track_index_order` is enabled.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct VersionInfo {
+    pub index_etag: Option<String>,
+    pub export_fingerprint: Option<String>,
+    pub image_cursor: Option<usize>,
+    pub last_version: Option<String>,
+    pub index_hash: Option<String>,
+    pub index_order: Option<Vec<String>>,
+}
+
+/// Struct that holds the hash of a downloaded export, persisted in `export_hash.json`.
+/// Older files stored only the hash as a string; those entries load with no kind.
+/// - `hash`: The hash of the export in the export index.
+/// - `kind`: The category of the export, as classified by `resource_kind`, if known.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "ExportHashEntry")]
+pub struct ExportHash {
+    pub hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+}
+
+/// The stored forms of an `ExportHash`, used to migrate older hash files.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExportHashEntry {
+    Legacy(String),
+    Current {
+        hash: String,
+        #[serde(default)]
+        kind: Option<String>,
+    },
+}
+
+impl From<ExportHashEntry> for ExportHash {
+    fn from(entry: ExportHashEntry) -> Self {
+        match entry {
+            ExportHashEntry::Legacy(hash) => ExportHash { hash, kind: None },
+            ExportHashEntry::Current { hash, kind } => ExportHash { hash, kind },
+        }
+    }
+}
+
+/// Struct that holds the hashes of a downloaded image, persisted in `image_hash.json`.
+/// Older files stored only the manifest hash as a string; those entries load with no content hash.
+/// - `manifest_hash`: The hash of the image in the export manifest.
+/// - `content_hash`: The BLAKE3 hash of the downloaded original, if known.
+/// - `kind`: The category of the image, as classified by `resource_kind`, if known.
+/// - `perceptual_hash`: The dHash of the decoded original as 16 hex digits, if `COMPUTE_PHASH` was enabled.
+/// - `dimensions`: The width and height of the downloaded original, before it was resized, if `STORE_DIMENSIONS` was enabled.
+/// - `formats`: The formats each size was written in, keyed by size (`512` is the original).
+///   Empty for entries written before formats were recorded, which were always PNG.
+/// - `output_hashes`: The BLAKE3 hashes of the outputs as written, keyed by `output_key`, e.g. `64.png`.
+///   Empty for entries written before output hashes were recorded.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "ImageHashEntry")]
+pub struct ImageHash {
+    pub manifest_hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub perceptual_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<(u32, u32)>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub formats: BTreeMap<u32, Vec<OutputFormat>>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub output_hashes: BTreeMap<String, String>,
+}
+
+impl ImageHash {
+    /// The key of an output in `output_hashes`, e.g. `64.png`.
+    pub fn output_key(size: u32, format: OutputFormat) -> String {
+        format!("{}.{}", size, format.extension())
+    }
+
+    /// The primary format the given size was last written in, if it was written at all.
+    pub fn format_for(&self, size: u32) -> Option<OutputFormat> {
+        self.formats_for(size).first().copied()
+    }
+
+    /// Every format the given size was last written in, or none if it wasn't written at all.
+    pub fn formats_for(&self, size: u32) -> Vec<OutputFormat> {
+        if self.formats.is_empty() {
+            vec![OutputFormat::Png]
+        } else {
+            self.formats.get(&size).cloned().unwrap_or_default()
+        }
+    }
+}
+
+/// The stored forms of an `ImageHash`, used to migrate older hash files.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ImageHashEntry {
+    Legacy(String),
+    Current {
+        manifest_hash: String,
+        #[serde(default)]
+        content_hash: Option<String>,
+        #[serde(default)]
+        kind: Option<String>,
+        #[serde(default)]
+        perceptual_hash: Option<String>,
+        #[serde(default)]
+        dimensions: Option<(u32, u32)>,
+        // Untagged variants can't parse JSON object keys as numbers, so sizes are parsed below.
+        #[serde(default)]
+        formats: BTreeMap<StoredSize, StoredFormats>,
+        #[serde(default)]
+        output_hashes: BTreeMap<String, String>,
+    },
+}
+
+/// A stored image size, which is a string key in JSON but a number in MessagePack.
+#[derive(Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(untagged)]
+enum StoredSize {
+    Number(u32),
+    Text(String),
+}
+
+/// The stored formats of one image size. Entries written before `OUTPUT_FORMATS` held a single format.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredFormats {
+    Single(OutputFormat),
+    Set(Vec<OutputFormat>),
+}
+
+impl From<ImageHashEntry> for ImageHash {
+    fn from(entry: ImageHashEntry) -> Self {
+        match entry {
+            ImageHashEntry::Legacy(manifest_hash) => ImageHash {
+                manifest_hash,
+                content_hash: None,
+                kind: None,
+                perceptual_hash: None,
+                dimensions: None,
+                formats: BTreeMap::new(),
+                output_hashes: BTreeMap::new(),
+            },
+            ImageHashEntry::Current {
+                manifest_hash,
+                content_hash,
+                kind,
+                perceptual_hash,
+                dimensions,
+                formats,
+                output_hashes,
+            } => ImageHash {
+                manifest_hash,
+                content_hash,
+                kind,
+                perceptual_hash,
+                dimensions,
+                formats: formats
+                    .into_iter()
+                    .filter_map(|(size, formats)| {
+                        let formats = match formats {
+                            StoredFormats::Single(format) => vec![format],
+                            StoredFormats::Set(formats) => formats,
+                        };
+                        let size = match size {
+                            StoredSize::Number(size) => size,
+                            StoredSize::Text(size) => size.parse().ok()?,
+                        };
+                        Some((size, formats))
+                    })
+                    .collect(),
+                output_hashes,
+            },
+        }
+    }
+}
+
+/// Struct that holds the differences between two hash maps, as lists of resource names.
+/// - `added`: Resources only present in the new map.
+/// - `updated`: Resources present in both maps with a different hash.
+/// - `removed`: Resources only present in the old map.
+/// - `unchanged`: Resources present in both maps with the same hash.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct HashDiff {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub removed: Vec<String>,
+    pub unchanged: Vec<String>,
+}
+
+impl HashDiff {
+    /// Whether any resource was added, updated, or removed.
+    pub fn has_changes(&self) -> bool {
+        !(self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty())
+    }
+}
+
+impl fmt::Display for HashDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} updated, {} removed, {} unchanged",
+            self.added.len(),
+            self.updated.len(),
+            self.removed.len(),
+            self.unchanged.len()
+        )
+    }
+}
+
 /// Takes in regex captures and returns an escaped representation of the match.
 ///
 /// # Arguments
@@ -74,12 +581,12 @@ pub fn escape_match(captures: &Captures) -> &'static str {
 /// Splits a string into a `Resource` struct containing a name and a hash.
 ///
 /// # Arguments
-/// - `string` - A `String` expected to contain a name and a hash, separated by `"!"`.
+/// - `string` - A string expected to contain a name and a hash, separated by `"!"`.
 ///
 /// # Returns
 /// - `Ok(Resource)` - If the string is successfully split into `name` and `hash`.
 /// - `panic!` - If the delimiter `"!"` is missing in the input string.
-pub fn split_string_to_resource(string: &String) -> Result<Resource, Box<dyn Error>> {
+pub fn split_string_to_resource(string: &str) -> Result<Resource, Box<dyn Error>> {
     let Some((name, hash)) = string.split_once("!") else {
         panic!(
             "Attempted to split a resource, but missing hash? ({})",
@@ -93,59 +600,742 @@ pub fn split_string_to_resource(string: &String) -> Result<Resource, Box<dyn Err
     })
 }
 
-/// Loads a hash map from a JSON file if it exists; otherwise, returns an empty map.
+/// Rules that classify an image by a segment of its unique name, checked in order.
+const IMAGE_KIND_RULES: &[(&str, &str)] = &[
+    ("/Powersuits/", "Warframe"),
+    ("/Weapons/", "Weapon"),
+    ("/Upgrades/", "Mod"),
+    ("/Sentinels/", "Companion"),
+    ("/Pets/", "Companion"),
+    ("/Types/Items/", "Item"),
+    ("/Interface/Icons/", "Icon"),
+];
+
+/// Classifies a resource into a category, for reporting.
+///
+/// # Arguments
+/// - `name` - The resource name: an export file name (e.g. `ExportWeapons_en.json`) or an image unique name
+///   (e.g. `/Lotus/Interface/Icons/Foo.png`).
+///
+/// # Returns
+/// - The export category without its prefix and language (e.g. `Weapons`), the first matching
+///   `IMAGE_KIND_RULES` entry for images (e.g. `Icon`), or `Other`.
+pub fn resource_kind(name: &str) -> String {
+    if name.starts_with('/') {
+        return IMAGE_KIND_RULES
+            .iter()
+            .find(|(segment, _)| name.contains(segment))
+            .map_or("Other", |(_, kind)| kind)
+            .to_string();
+    }
+
+    name.strip_prefix("Export")
+        .and_then(|rest| rest.split(['_', '.']).next())
+        .filter(|category| !category.is_empty())
+        .unwrap_or("Other")
+        .to_string()
+}
+
+/// Parses the content of `ExportManifest.json`.
+///
+/// # Arguments
+/// - `content` - The content of the export manifest.
+///
+/// # Returns
+/// - `Ok(ExportManifest)` - If the manifest has the expected shape; unknown fields are ignored.
+/// - `Err` - Naming the missing or mistyped field (e.g. `textureLocation`), if the shape has changed.
+pub fn parse_export_manifest(content: &str) -> Result<ExportManifest, Box<dyn Error>> {
+    serde_json::from_str(content).map_err(|e| {
+        format!(
+            "ExportManifest.json does not have the expected shape ({}); \
+             the manifest format may have changed upstream",
+            e
+        )
+        .into()
+    })
+}
+
+/// Iterates over the resource entries of a decompressed export index.
+///
+/// # Arguments
+/// - `index` - The decompressed export index content.
+///
+/// # Returns
+/// - An iterator of trimmed, non-empty lines; blank lines and stray `\r` characters
+///   (e.g. from mixed CRLF/LF line endings) are skipped.
+pub fn index_entries(index: &str) -> impl Iterator<Item = &str> {
+    index.lines().map(str::trim).filter(|line| !line.is_empty())
+}
+
+/// Whether the resources two export indexes have in common are listed in a different order.
+/// Added and removed resources are ignored, so a reorder is caught even alongside other changes.
+///
+/// # Arguments
+/// - `previous` - The resource names of the previous index, in order.
+/// - `current` - The resource names of the current index, in order.
+///
+/// # Returns
+/// - `true` if the shared resources appear in a different relative order.
+pub fn index_order_changed(previous: &[String], current: &[String]) -> bool {
+    let previous_names: HashSet<&String> = previous.iter().collect();
+    let current_names: HashSet<&String> = current.iter().collect();
+    previous
+        .iter()
+        .filter(|name| current_names.contains(name))
+        .ne(current.iter().filter(|name| previous_names.contains(name)))
+}
+
+/// Recursively renames every object key of an export to the given naming convention.
+///
+/// # Arguments
+/// - `value` - The parsed export.
+/// - `case` - The naming convention to rename keys to.
+///
+/// # Returns
+/// - The export with renamed keys; values are left untouched.
+pub fn transform_keys(value: serde_json::Value, case: KeyCase) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) if case != KeyCase::Verbatim => map
+            .into_iter()
+            .map(|(key, value)| (rename_key(&key, case), transform_keys(value, case)))
+            .collect(),
+        serde_json::Value::Array(items) if case != KeyCase::Verbatim => items
+            .into_iter()
+            .map(|item| transform_keys(item, case))
+            .collect(),
+        other => other,
+    }
+}
+
+/// Renames a single key, splitting it into words on separators and case boundaries.
+/// For example, `uniqueName`, `UniqueName`, and `unique_name` are all the same two words.
+fn rename_key(key: &str, case: KeyCase) -> String {
+    let mut words: Vec<String> = Vec::new();
+    let chars: Vec<char> = key.chars().collect();
+    let mut word = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c == '_' || c == '-' || c == ' ' {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+
+        // A new word starts at `aB`, and at the last capital of a run like `HTTPServer`.
+        let previous = i.checked_sub(1).map(|j| chars[j]);
+        let next = chars.get(i + 1);
+        let boundary = c.is_uppercase()
+            && previous.is_some_and(|previous| {
+                previous.is_lowercase()
+                    || previous.is_ascii_digit()
+                    || (previous.is_uppercase() && next.is_some_and(|next| next.is_lowercase()))
+            });
+        if boundary && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        word.push(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    match case {
+        KeyCase::Verbatim => key.to_string(),
+        KeyCase::SnakeCase => words
+            .iter()
+            .map(|word| word.to_lowercase())
+            .collect::<Vec<_>>()
+            .join("_"),
+        KeyCase::CamelCase => words
+            .iter()
+            .enumerate()
+            .map(|(i, word)| {
+                let lower = word.to_lowercase();
+                let mut chars = lower.chars();
+                match chars.next() {
+                    Some(first) if i > 0 => first.to_uppercase().chain(chars).collect(),
+                    _ => lower,
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Computes the difference hash (dHash) of an image, a perceptual hash that changes little when the
+/// image is rescaled or recompressed, so visually similar images differ in only a few bits.
+///
+/// # Arguments
+/// - `image` - The decoded image.
+///
+/// # Returns
+/// - A 64-bit hash, where each bit records whether a pixel of a 9x8 grayscale thumbnail is darker
+///   than its right neighbour.
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let thumbnail = image::imageops::resize(
+        &image.to_luma8(),
+        9,
+        8,
+        image::imageops::FilterType::Triangle,
+    );
+
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if thumbnail.get_pixel(x, y)[0] < thumbnail.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+
+    hash
+}
+
+/// Unwraps an export that is an object with a single top-level key, e.g. `{"ExportWeapons": [...]}`.
+///
+/// # Arguments
+/// - `value` - The parsed export.
+///
+/// # Returns
+/// - `Ok(inner)` with the value of the single key, or `Err(value)` unchanged if the export has another shape.
+pub fn unwrap_export(value: serde_json::Value) -> Result<serde_json::Value, serde_json::Value> {
+    match value {
+        serde_json::Value::Object(map) if map.len() == 1 => Ok(map
+            .into_iter()
+            .next()
+            .map(|(_, inner)| inner)
+            .unwrap_or_default()),
+        other => Err(other),
+    }
+}
+
+/// Transforms an export with a jq filter, e.g. `.ExportWeapons | map({uniqueName, name})`.
+///
+/// # Arguments
+/// - `content` - The export content, as JSON.
+/// - `filter` - The jq program to run on it.
+///
+/// # Returns
+/// - The single value the filter produced, or an array of its values if it produced none or several.
+pub fn apply_filter(content: &str, filter: &str) -> Result<serde_json::Value, Box<dyn Error>> {
+    let input = jaq_json::read::parse_single(content.as_bytes())?;
+
+    let defs = jaq_core::defs()
+        .chain(jaq_std::defs())
+        .chain(jaq_json::defs());
+    let funs = jaq_core::funs()
+        .chain(jaq_std::funs())
+        .chain(jaq_json::funs());
+    let arena = Arena::default();
+    let program = File {
+        code: filter,
+        path: (),
+    };
+    let modules = Loader::new(defs).load(&arena, program).map_err(|errors| {
+        let errors: Vec<_> = errors.into_iter().map(|(_, error)| error).collect();
+        format!("Failed to parse filter {}: {:?}", filter, errors)
+    })?;
+    let compiled = Compiler::default()
+        .with_funs(funs)
+        .compile(modules)
+        .map_err(|errors| {
+            let errors: Vec<_> = errors.into_iter().map(|(_, error)| error).collect();
+            format!("Failed to compile filter {}: {:?}", filter, errors)
+        })?;
+
+    let ctx = Ctx::<data::JustLut<jaq_json::Val>>::new(&compiled.lut, Vars::new([]));
+    let mut outputs = compiled
+        .id
+        .run((ctx, input))
+        .map(|output| {
+            let output =
+                unwrap_valr(output).map_err(|e| format!("Filter {} failed: {}", filter, e))?;
+            Ok(serde_json::from_str(&output.to_string())?)
+        })
+        .collect::<Result<Vec<serde_json::Value>, Box<dyn Error>>>()?;
+
+    if outputs.len() == 1 {
+        Ok(outputs.remove(0))
+    } else {
+        Ok(serde_json::Value::Array(outputs))
+    }
+}
+
+/// Renders an export as newline-delimited JSON, one array element per line.
+///
+/// # Arguments
+/// - `value` - The parsed export, either a top-level array or an object wrapping a single array
+///   (e.g. `{"ExportWeapons": [...]}`).
+///
+/// # Returns
+/// - `Some(String)` with one compact JSON value per line, or `None` if the export has no such array.
+pub fn to_ndjson(value: &serde_json::Value) -> Option<String> {
+    let items = match value {
+        serde_json::Value::Array(items) => items,
+        serde_json::Value::Object(map) if map.len() == 1 => map.values().next()?.as_array()?,
+        _ => return None,
+    };
+
+    let mut ndjson = String::new();
+    for item in items {
+        ndjson.push_str(&item.to_string());
+        ndjson.push('\n');
+    }
+
+    Some(ndjson)
+}
+
+/// Parses a `#RRGGBB` hex color.
+///
+/// # Arguments
+/// - `color` - The color string, with or without the leading `#`.
+///
+/// # Returns
+/// - `Some([r, g, b])` if the string is a valid 6-digit hex color, or `None` otherwise.
+pub fn parse_hex_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// Composites an RGBA image over a solid background color, producing an opaque RGB image.
+///
+/// # Arguments
+/// - `image` - The source image with transparency.
+/// - `background` - The `[r, g, b]` background color.
+///
+/// # Returns
+/// - An `RgbImage` with the same dimensions as the source.
+pub fn flatten_background(image: &RgbaImage, background: [u8; 3]) -> RgbImage {
+    RgbImage::from_fn(image.width(), image.height(), |x, y| {
+        let [r, g, b, a] = image.get_pixel(x, y).0;
+        let blend = |fg: u8, bg: u8| {
+            ((fg as u32 * a as u32 + bg as u32 * (255 - a as u32) + 127) / 255) as u8
+        };
+        image::Rgb([
+            blend(r, background[0]),
+            blend(g, background[1]),
+            blend(b, background[2]),
+        ])
+    })
+}
+
+/// Compares two hash maps of resource name to hash.
 ///
 /// # Arguments
-/// - `file_path`: Path to the JSON file containing the hash map.
+/// - `old`: The previously stored hash map.
+/// - `new`: The hash map built from the latest index or manifest.
 ///
 /// # Returns
-/// - A `BTreeMap` containing the key-value pairs from the JSON file, or an empty map if the file doesn't exist.
-pub async fn load_hash_map_from_file(
+/// - A `HashDiff` with every resource name from both maps sorted into exactly one category.
+pub fn diff_hash_maps(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> HashDiff {
+    let mut diff = HashDiff::default();
+
+    for (name, hash) in new {
+        match old.get(name) {
+            None => diff.added.push(name.clone()),
+            Some(old_hash) if old_hash != hash => diff.updated.push(name.clone()),
+            Some(..) => diff.unchanged.push(name.clone()),
+        }
+    }
+
+    diff.removed = old
+        .keys()
+        .filter(|name| !new.contains_key(*name))
+        .cloned()
+        .collect();
+
+    diff
+}
+
+/// Loads a hash map from a JSON or MessagePack file if it exists; otherwise, returns an empty map.
+/// Files ending in `.msgpack` are read as MessagePack, and all others as JSON.
+///
+/// # Arguments
+/// - `file_path`: Path to the file containing the hash map.
+///
+/// # Returns
+/// - A `BTreeMap` containing the key-value pairs from the file, or an empty map if the file doesn't exist.
+pub async fn load_hash_map_from_file<T: DeserializeOwned>(
     file_path: &str,
-) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+) -> Result<BTreeMap<String, T>, Box<dyn Error>> {
     if Path::new(file_path).is_file() {
-        let existing_hashes = fs::read_to_string(file_path).await?;
-        let map = serde_json::from_str(&existing_hashes)?;
+        let existing_hashes = fs::read(file_path).await?;
+        let map = if file_path.ends_with(".msgpack") {
+            rmp_serde::from_slice(&existing_hashes)?
+        } else {
+            serde_json::from_slice(&existing_hashes)?
+        };
         return Ok(map);
     }
 
     Ok(BTreeMap::new())
 }
 
-/// Resizes an image to the specified square dimensions and encodes it as PNG.
+/// Loads the ETags stored for resources, keyed by resource name like the hash maps; otherwise, returns an empty map.
+///
+/// # Arguments
+/// - `file_path`: Path to the file containing the ETags.
+///
+/// # Returns
+/// - A `BTreeMap` of resource names to the ETag of their last download, or an empty map if the file doesn't exist.
+pub async fn load_etag_map_from_file(
+    file_path: &str,
+) -> Result<BTreeMap<String, String>, Box<dyn Error>> {
+    load_hash_map_from_file(file_path).await
+}
+
+/// Encodes a hash map for saving.
 ///
 /// # Arguments
-/// - `src_image` - A reference to the source image to resize.
+/// - `map`: The hash map to encode.
+/// - `format`: The encoding to use.
+///
+/// # Returns
+/// - The encoded hash map. MessagePack structs are encoded with their field names, as optional fields may be omitted.
+pub fn encode_hash_map<T: Serialize + ?Sized>(
+    map: &T,
+    format: HashFormat,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    Ok(match format {
+        HashFormat::Json => serde_json::to_vec(map)?,
+        HashFormat::MsgPack => rmp_serde::to_vec_named(map)?,
+    })
+}
+
+/// Loads the run state from a JSON file if it exists; otherwise, returns an empty state.
+///
+/// # Arguments
+/// - `file_path`: Path to the JSON file containing the run state.
+///
+/// # Returns
+/// - A `VersionInfo` read from the JSON file, or the default if the file doesn't exist.
+pub async fn load_version_from_file(file_path: &str) -> Result<VersionInfo, Box<dyn Error>> {
+    if Path::new(file_path).is_file() {
+        let existing_version = fs::read_to_string(file_path).await?;
+        let version = serde_json::from_str(&existing_version)?;
+        return Ok(version);
+    }
+
+    Ok(VersionInfo::default())
+}
+
+/// Resizes an image to the specified square dimensions and encodes it.
+///
+/// # Arguments
+/// - `src_image` - A reference to the source image to resize: `U8x4` (RGBA), `U8x3` (RGB), `U8x2` (grayscale with alpha) or `U8` (grayscale).
 /// - `size` - The desired output size (width and height, in pixels).
+/// - `format` - The encoding of the output image.
+/// - `filter` - The filter to downscale with.
+/// - `sharpen` - The sigma of an unsharp mask to apply after resizing, if any.
 ///
 /// # Returns
-/// - A `Vec<u8>` with PNG-encoded image bytes.
+/// - A `Vec<u8>` with encoded image bytes, in the same color type as the source.
 pub async fn resize_image(
     src_image: &Image<'static>,
     size: u32,
+    format: OutputFormat,
+    filter: ResizeFilter,
+    sharpen: Option<f32>,
 ) -> Result<Vec<u8>, Box<dyn Error>> {
-    let mut dst_image = Image::new(size, size, PixelType::U8x4);
+    resize_image_blocking(src_image, size, format, filter, sharpen).map_err(|e| e as Box<dyn Error>)
+}
+
+/// Resizes an image to the specified square dimensions and encodes it, on the current thread.
+/// Suitable for `spawn_blocking`, as the error type can be sent between threads.
+///
+/// # Arguments
+/// - `src_image` - A reference to the source image to resize: `U8x4` (RGBA), `U8x3` (RGB), `U8x2` (grayscale with alpha) or `U8` (grayscale).
+/// - `size` - The desired output size (width and height, in pixels).
+/// - `format` - The encoding of the output image.
+/// - `filter` - The filter to downscale with.
+/// - `sharpen` - The sigma of an unsharp mask to apply after resizing, if any.
+///
+/// # Returns
+/// - A `Vec<u8>` with encoded image bytes, in the same color type as the source.
+pub fn resize_image_blocking(
+    src_image: &Image<'static>,
+    size: u32,
+    format: OutputFormat,
+    filter: ResizeFilter,
+    sharpen: Option<f32>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let color_type = match src_image.pixel_type() {
+        PixelType::U8x4 => image::ExtendedColorType::Rgba8,
+        PixelType::U8x3 => image::ExtendedColorType::Rgb8,
+        PixelType::U8x2 => image::ExtendedColorType::La8,
+        PixelType::U8 => image::ExtendedColorType::L8,
+        other => return Err(format!("Unsupported pixel type: {:?}", other).into()),
+    };
+
+    let mut dst_image = Image::new(size, size, src_image.pixel_type());
     let mut resizer = Resizer::new();
 
     resizer
         .resize(
             &src_image.copy(),
             &mut dst_image,
-            &ResizeOptions::new().resize_alg(fast_image_resize::ResizeAlg::Interpolation(
-                fast_image_resize::FilterType::Lanczos3,
-            )),
+            &ResizeOptions::new().resize_alg(filter.resize_alg()),
         )
         .map_err(|e| format!("Resize failed: {:?}", e))?;
 
+    // Downscaling softens edges, which an unsharp mask restores. A threshold of 0 sharpens every edge.
+    let mut buffer = dst_image.into_vec();
+    if let Some(sigma) = sharpen {
+        buffer = match color_type {
+            image::ExtendedColorType::Rgba8 => RgbaImage::from_raw(size, size, buffer)
+                .map(|image| imageops::unsharpen(&image, sigma, 0).into_raw()),
+            image::ExtendedColorType::Rgb8 => RgbImage::from_raw(size, size, buffer)
+                .map(|image| imageops::unsharpen(&image, sigma, 0).into_raw()),
+            image::ExtendedColorType::La8 => GrayAlphaImage::from_raw(size, size, buffer)
+                .map(|image| imageops::unsharpen(&image, sigma, 0).into_raw()),
+            _ => GrayImage::from_raw(size, size, buffer)
+                .map(|image| imageops::unsharpen(&image, sigma, 0).into_raw()),
+        }
+        .ok_or("Resized image has an unexpected size")?;
+    }
+
     let mut result_buf = BufWriter::new(Vec::new());
-    PngEncoder::new(&mut result_buf)
-        .write_image(
-            dst_image.buffer(),
-            size,
-            size,
-            image::ExtendedColorType::Rgba8,
-        )
-        .map_err(|e| format!("Failed to encode image: {}", e))?;
+    match format {
+        OutputFormat::Png => {
+            PngEncoder::new(&mut result_buf).write_image(&buffer, size, size, color_type)
+        }
+        OutputFormat::WebP => {
+            WebPEncoder::new_lossless(&mut result_buf).write_image(&buffer, size, size, color_type)
+        }
+    }
+    .map_err(|e| format!("Failed to encode image: {}", e))?;
 
     Ok(result_buf.into_inner().unwrap())
 }
+
+/// Resizes every image in a directory to each of the given sizes, with the same pipeline as synced images.
+/// Each variant is written to `{dst_dir}/{size}x{size}/{stem}.{extension}`, with the filter a sync uses by
+/// default for that size. Subdirectories and files that can't be decoded as images are skipped.
+///
+/// # Arguments
+/// - `src_dir`: The directory of source images.
+/// - `dst_dir`: The directory to write the resized variants to, created if missing.
+/// - `sizes`: The sizes to resize to, e.g. `IMAGE_SIZES`.
+/// - `format`: The encoding of the resized variants.
+///
+/// # Returns
+/// - The number of source images that were resized.
+pub async fn resize_directory(
+    src_dir: &str,
+    dst_dir: &str,
+    sizes: &[u32],
+    format: OutputFormat,
+) -> Result<usize, Box<dyn Error>> {
+    let defaults = SyncConfig::default();
+    for size in sizes {
+        fs::create_dir_all(format!("{}/{}x{}", dst_dir, size, size)).await?;
+    }
+
+    let mut resized = 0;
+    let mut entries = fs::read_dir(src_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if !entry.file_type().await?.is_file() {
+            continue;
+        }
+        let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let Ok(decoded) = image::load_from_memory(&fs::read(&path).await?) else {
+            continue;
+        };
+
+        let (width, height) = (decoded.width(), decoded.height());
+        let raw_image = Image::from_vec_u8(
+            width,
+            height,
+            decoded.to_rgba8().into_raw(),
+            PixelType::U8x4,
+        )?;
+        for size in sizes {
+            let resized_buf =
+                resize_image(&raw_image, *size, format, defaults.filter_for(*size), None).await?;
+            let dst_path = format!(
+                "{}/{}x{}/{}.{}",
+                dst_dir,
+                size,
+                size,
+                stem,
+                format.extension()
+            );
+            fs::write(&dst_path, resized_buf).await?;
+        }
+        resized += 1;
+    }
+
+    Ok(resized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_map(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries
+            .iter()
+            .map(|(name, hash)| (name.to_string(), hash.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn diff_hash_maps_sorts_every_change() {
+        let old = hash_map(&[("Kept", "a"), ("Changed", "b"), ("Gone", "c")]);
+        let new = hash_map(&[("Kept", "a"), ("Changed", "B"), ("New", "d")]);
+
+        let diff = diff_hash_maps(&old, &new);
+        assert_eq!(diff.added, ["New"]);
+        assert_eq!(diff.updated, ["Changed"]);
+        assert_eq!(diff.removed, ["Gone"]);
+        assert_eq!(diff.unchanged, ["Kept"]);
+        assert!(diff.has_changes());
+    }
+
+    #[test]
+    fn diff_hash_maps_without_changes() {
+        let map = hash_map(&[("First", "a"), ("Second", "b")]);
+
+        let diff = diff_hash_maps(&map, &map);
+        assert!(!diff.has_changes());
+        assert_eq!(diff.unchanged, ["First", "Second"]);
+        assert_eq!(
+            diff_hash_maps(&BTreeMap::new(), &BTreeMap::new()),
+            HashDiff::default()
+        );
+    }
+
+    #[test]
+    fn index_entries_skips_blank_lines_and_carriage_returns() {
+        let index = "ExportWeapons_en.json!00_a\r\n\r\n  \nExportManifest.json!00_b\r\r\n\n\n";
+
+        let entries: Vec<&str> = index_entries(index).collect();
+        assert_eq!(
+            entries,
+            ["ExportWeapons_en.json!00_a", "ExportManifest.json!00_b"]
+        );
+    }
+
+    #[test]
+    fn parse_export_manifest_names_a_missing_field() {
+        let content = r#"{"Manifest":[{"uniqueName":"/Lotus/Foo"}]}"#;
+
+        let err = parse_export_manifest(content).unwrap_err().to_string();
+        assert!(err.contains("missing field `textureLocation`"), "{}", err);
+    }
+
+    /// A 512px grayscale image of a light square on a dark background. Its top-left edges sit a pixel from
+    /// where Lanczos3 samples for a 32px output, so they ring, overshooting the range of the source.
+    fn ringing_fixture() -> Image<'static> {
+        let pixels = (0..512 * 512)
+            .map(|i| {
+                let (x, y) = (i % 512, i / 512);
+                if (135..384).contains(&x) && (135..384).contains(&y) {
+                    192
+                } else {
+                    64
+                }
+            })
+            .collect();
+        Image::from_vec_u8(512, 512, pixels, PixelType::U8).unwrap()
+    }
+
+    fn decode_gray(encoded: &[u8]) -> GrayImage {
+        image::load_from_memory(encoded).unwrap().to_luma8()
+    }
+
+    #[test]
+    fn resize_image_blocking_avoids_ringing_at_32px() {
+        let fixture = ringing_fixture();
+        let filter = SyncConfig::default().filter_for(32);
+        let within_source = |encoded: &[u8]| {
+            decode_gray(encoded)
+                .pixels()
+                .all(|pixel| (64..=192).contains(&pixel[0]))
+        };
+
+        let lanczos = resize_image_blocking(
+            &fixture,
+            32,
+            OutputFormat::Png,
+            ResizeFilter::Lanczos3,
+            None,
+        )
+        .unwrap();
+        assert!(
+            !within_source(&lanczos),
+            "fixture should ring with Lanczos3"
+        );
+
+        let resized = resize_image_blocking(&fixture, 32, OutputFormat::Png, filter, None).unwrap();
+        assert_eq!(filter, ResizeFilter::Area);
+        assert!(within_source(&resized));
+    }
+
+    #[test]
+    fn resize_image_blocking_sharpens_a_valid_png() {
+        let rgba = RgbaImage::from_fn(512, 512, |x, y| {
+            image::Rgba([
+                (x / 2) as u8,
+                (y / 2) as u8,
+                128,
+                if x < 256 { 255 } else { 128 },
+            ])
+        });
+        let source = Image::from_vec_u8(512, 512, rgba.into_raw(), PixelType::U8x4).unwrap();
+
+        let sharpened = resize_image_blocking(
+            &source,
+            64,
+            OutputFormat::Png,
+            ResizeFilter::Lanczos3,
+            Some(1.0),
+        )
+        .unwrap();
+        assert_eq!(
+            image::guess_format(&sharpened).unwrap(),
+            image::ImageFormat::Png
+        );
+        let decoded = image::load_from_memory(&sharpened).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (64, 64));
+        assert_eq!(decoded.color(), image::ColorType::Rgba8);
+    }
+
+    #[tokio::test]
+    async fn resize_directory_writes_every_variant() {
+        let root =
+            std::env::temp_dir().join(format!("warframe-exports-resize-{}", std::process::id()));
+        let (src_dir, dst_dir) = (root.join("src"), root.join("dst"));
+        std::fs::create_dir_all(src_dir.join("nested")).unwrap();
+        GrayImage::from_pixel(100, 100, image::Luma([200]))
+            .save(src_dir.join("Foo.png"))
+            .unwrap();
+        std::fs::write(src_dir.join("notes.txt"), "not an image").unwrap();
+
+        let resized = resize_directory(
+            src_dir.to_str().unwrap(),
+            dst_dir.to_str().unwrap(),
+            &[64, 32],
+            OutputFormat::WebP,
+        )
+        .await
+        .unwrap();
+        assert_eq!(resized, 1);
+        for size in [64, 32] {
+            let size_dir = dst_dir.join(format!("{}x{}", size, size));
+            let variant = image::open(size_dir.join("Foo.webp")).unwrap();
+            assert_eq!((variant.width(), variant.height()), (size, size));
+            assert_eq!(std::fs::read_dir(size_dir).unwrap().count(), 1);
+        }
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::SyncConfig;
+
+/// The log file of the current run, if one is open.
+static RUN_LOG: Mutex<Option<File>> = Mutex::new(None);
+
+/// Prints a line to stdout, and appends it to the run log if one is open.
+macro_rules! log {
+    ($($arg:tt)*) => {{
+        let line = format!($($arg)*);
+        println!("{}", line);
+        $crate::log::append(&line);
+    }};
+}
+
+pub(crate) use log;
+
+/// Appends a line to the run log, if one is open.
+/// Failures are ignored, as the line has already been printed.
+pub fn append(line: &str) {
+    if let Ok(mut run_log) = RUN_LOG.lock() {
+        if let Some(file) = run_log.as_mut() {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+}
+
+/// Opens `{output_dir}/logs/run-{started_at}.log` as the run log, and deletes all but the newest logs.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the output directory and how many logs to keep.
+/// - `started_at`: When the run started, in seconds since the Unix epoch.
+///
+/// # Returns
+/// - `Ok(())` if the log was opened, or if run logs are disabled.
+pub fn start_run_log(config: &SyncConfig, started_at: u64) -> Result<(), Box<dyn Error>> {
+    if config.keep_run_logs == 0 {
+        return Ok(());
+    }
+
+    let log_dir = format!("{}/logs", config.output_dir);
+    fs::create_dir_all(&log_dir)?;
+    let file = File::create(format!("{}/run-{}.log", log_dir, started_at))?;
+    *RUN_LOG.lock().map_err(|e| e.to_string())? = Some(file);
+
+    // Timestamps have the same number of digits, so names sort chronologically.
+    let mut logs: Vec<_> = fs::read_dir(&log_dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| is_run_log(path))
+        .collect();
+    logs.sort();
+    let excess = logs.len().saturating_sub(config.keep_run_logs);
+    for path in &logs[..excess] {
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+/// Closes the run log, if one is open.
+pub fn finish_run_log() {
+    if let Ok(mut run_log) = RUN_LOG.lock() {
+        *run_log = None;
+    }
+}
+
+/// Whether a path is a log written by `start_run_log`.
+fn is_run_log(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("run-") && name.ends_with(".log"))
+}
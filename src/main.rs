@@ -1,371 +1,330 @@
-use fast_image_resize::images::Image;
-use fast_image_resize::PixelType;
-use image::ImageReader;
-use reqwest::Client;
-use reqwest::Url;
-use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
-use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
-use std::collections::BTreeMap;
+use clap::{Parser, Subcommand};
+#[cfg(feature = "serve")]
 use std::env;
 use std::error::Error;
-use std::io::{BufReader, Cursor};
-use std::path::Path;
-use std::string::ToString;
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::process::ExitCode;
+#[cfg(feature = "serve")]
 use std::sync::Arc;
-use tokio::fs;
-use tokio::sync::Mutex;
-use tokio::task::JoinSet;
+use std::time::Duration;
+use tokio::signal;
+use tokio::sync::watch;
+#[cfg(feature = "serve")]
+use tokio::sync::RwLock;
+use tokio::time::sleep;
 
 use warframe_exports::{
-    // Functions
-    escape_match,
-    load_hash_map_from_file,
-    resize_image,
-    split_string_to_resource,
-
-    // Structs
-    DownloadConfig,
-    ExportManifest,
-    ExportManifestItem,
-    Resource,
-
-    // Constants
-    IMAGE_SIZES,
-    LZMA_URL_PATH,
-    MANIFEST_PATH,
-    PUBLIC_EXPORT_PATH,
-    RE_ESCAPES,
-    UNWRAP_NONE,
-    WARFRAME_CONTENT_URL,
-    WARFRAME_ORIGIN_URL,
+    estimate, inspect, run_sync, DiskFull, FileSystemSink, OutputFormat, RunReport, SyncConfig,
+    SyncOutcome,
 };
+#[cfg(feature = "serve")]
+use warframe_exports::{serve_health, HealthStatus};
+
+/// Exit code used when the run completed without changing anything.
+const EXIT_UNCHANGED: u8 = 75;
+/// Exit code used when the run stopped early because `MAX_RUNTIME_SECS` was exceeded.
+const EXIT_TIMED_OUT: u8 = 124;
+/// Exit code used when the run stopped because the output volume is full.
+const EXIT_DISK_FULL: u8 = 74;
+/// Exit code used when the run stopped early because `SIGINT` or `SIGTERM` was received.
+const EXIT_INTERRUPTED: u8 = 130;
+
+/// Downloads Warframe public export data, like manifests and images, from the Warframe content server.
+///
+/// Every option falls back to its environment variable, then the config file, then the built-in default.
+/// Options without a flag here can still be set through their environment variable or the config file.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// A TOML or JSON config file [env: CONFIG_FILE]
+    #[arg(long, global = true)]
+    config: Option<String>,
+
+    /// The output directory of the export files [env: OUTPUT_DIRECTORY]
+    #[arg(long, global = true)]
+    output_dir: Option<String>,
+
+    /// A separate directory for export files [env: EXPORT_DIRECTORY]
+    #[arg(long, global = true)]
+    export_dir: Option<String>,
+
+    /// A separate directory for images [env: IMAGE_DIRECTORY]
+    #[arg(long, global = true)]
+    image_dir: Option<String>,
+
+    /// The language of the exports to download [env: LANGUAGE]
+    #[arg(long, global = true)]
+    language: Option<String>,
+
+    /// Several languages to mirror, each into its own subdirectory [env: LANGUAGES]
+    #[arg(long, global = true, value_delimiter = ',')]
+    languages: Option<Vec<String>>,
+
+    /// The format images are written in: png or webp [env: OUTPUT_FORMAT]
+    #[arg(long, global = true)]
+    output_format: Option<OutputFormat>,
+
+    /// Only write these image sizes, e.g. 64,128 [env: ONLY_SIZES]
+    #[arg(long, global = true, value_delimiter = ',')]
+    only_sizes: Option<Vec<u32>>,
+
+    /// How many resources may be downloading at once, or 0 for no limit [env: MAX_CONCURRENT_DOWNLOADS]
+    #[arg(long, global = true)]
+    max_concurrent_downloads: Option<usize>,
+
+    /// How many images may be decoded and resized at once [env: MAX_CONCURRENT_DECODE]
+    #[arg(long, global = true)]
+    max_concurrent_decode: Option<usize>,
+
+    /// Abort the run once this many downloads failed, or 0 for no limit [env: MAX_FAILURES]
+    #[arg(long, global = true)]
+    max_failures: Option<usize>,
+
+    /// Stop the run early after this many seconds, saving progress [env: MAX_RUNTIME_SECS]
+    #[arg(long, global = true)]
+    max_runtime_secs: Option<u64>,
+
+    /// Run again every this many seconds instead of exiting [env: LOOP_INTERVAL_SECS]
+    #[arg(long, global = true)]
+    loop_interval_secs: Option<u64>,
+
+    /// Download every resource again, even if its hash is unchanged [env: FORCE]
+    #[arg(long, global = true)]
+    force: bool,
+
+    /// Only estimate the size of a full mirror, writing nothing, like `estimate`
+    #[arg(long, global = true)]
+    dry_run: bool,
+}
 
-#[tokio::main(flavor = "multi_thread")]
-async fn main() -> Result<(), Box<dyn Error>> {
-    // An HTTP client to share between all requests.
-    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
-    let client = Arc::new(
-        ClientBuilder::new(Client::new())
-            .with(RetryTransientMiddleware::new_with_policy(retry_policy))
-            .build(),
-    );
-
-    // Create output directory.
-    let output_dir = env::var("OUTPUT_DIRECTORY").unwrap_or("./output".to_string());
-
-    let storage_folders = [
-        format!("{}/", output_dir),
-        format!("{}/image", output_dir),
-        format!("{}/export", output_dir),
-    ];
-
-    let export_hash_location = format!("{}/export_hash.json", output_dir);
-    let image_hash_location = format!("{}/image_hash.json", output_dir);
-
-    // Create missing data folders.
-    for folder in &storage_folders {
-        if Path::new(folder).is_dir() == false {
-            println!("{} directory not found, initializing...", folder);
-            fs::create_dir(folder).await?;
-        }
-    }
+#[derive(Subcommand)]
+enum Command {
+    /// Estimate the size of a full image mirror, writing nothing to disk
+    Estimate,
+    /// Process a single image as a sync would and describe it, writing nothing to disk
+    Inspect {
+        /// The unique name of the image, e.g. /Lotus/Interface/Icons/Foo
+        #[arg(long)]
+        name: String,
+    },
+    /// Serve images over HTTP, resizing variants on demand
+    #[cfg(feature = "serve")]
+    Serve {
+        /// The address to listen on [env: SERVE_ADDRESS]
+        #[arg(long)]
+        address: Option<String>,
+    },
+}
 
-    // Create missing resize-directory data folders.
-    for size in IMAGE_SIZES {
-        let folder = format!("{}/{}x{}", &storage_folders[1], size, size);
-        if Path::new(&folder).is_dir() == false {
-            println!("{} directory not found, initializing...", folder);
-            fs::create_dir(folder).await?;
+impl Cli {
+    /// Overrides configuration values with any options that were passed.
+    ///
+    /// # Arguments
+    /// - `config`: The configuration loaded from the config file and environment variables.
+    ///
+    /// # Returns
+    /// - The `SyncConfig`, with the options applied over its current values.
+    fn apply(&self, config: SyncConfig) -> SyncConfig {
+        SyncConfig {
+            output_dir: self.output_dir.clone().unwrap_or(config.output_dir),
+            export_dir: self.export_dir.clone().or(config.export_dir),
+            image_dir: self.image_dir.clone().or(config.image_dir),
+            language: self.language.clone().unwrap_or(config.language),
+            languages: self.languages.clone().unwrap_or(config.languages),
+            output_format: self.output_format.unwrap_or(config.output_format),
+            only_sizes: self.only_sizes.clone().unwrap_or(config.only_sizes),
+            max_concurrent_downloads: self
+                .max_concurrent_downloads
+                .unwrap_or(config.max_concurrent_downloads),
+            max_concurrent_decode: self
+                .max_concurrent_decode
+                .unwrap_or(config.max_concurrent_decode),
+            max_failures: self.max_failures.unwrap_or(config.max_failures),
+            max_runtime: self
+                .max_runtime_secs
+                .map(Duration::from_secs)
+                .or(config.max_runtime),
+            loop_interval: self
+                .loop_interval_secs
+                .map(Duration::from_secs)
+                .or(config.loop_interval),
+            force: self.force || config.force,
+            ..config
         }
     }
+}
 
-    let mut updated_hash = false;
-    let mut updated_manifest = false;
-
-    let mut export_set: JoinSet<()> = JoinSet::new();
-    let mut export_hashes = Arc::new(Mutex::new(
-        load_hash_map_from_file(&export_hash_location).await?,
-    ));
-
-    let export_index = download_export_index(&client).await?;
-    let mut lines = export_index.lines();
-    while let Some(line) = lines.next() {
-        let (hash, manifest) = check_and_download_resource(
-            &client,
-            &mut export_hashes,
-            &mut export_set,
-            Arc::new(split_string_to_resource(&line.to_string())?),
-            Arc::new(DownloadConfig {
-                url: format!("{}{}/{}", WARFRAME_CONTENT_URL, MANIFEST_PATH, line),
-                path: storage_folders[2].clone(),
-                // Remove the last 31 characters, which is the ".json!" plus the 25-digit hash.
-                name: line[..(line.len() - 31)].to_string(),
-                as_text: true,
-            }),
-        )
-        .await?;
-
-        // Any hash got updated, only set once.
-        if hash {
-            updated_hash = true;
-            // Specifically, Manifest hash was updated.
-            if manifest {
-                updated_manifest = true;
-            }
+#[tokio::main(flavor = "multi_thread")]
+async fn main() -> Result<ExitCode, Box<dyn Error>> {
+    // Options take precedence over environment variables, which take precedence over the config file.
+    let cli = Cli::parse();
+    let config = cli.apply(SyncConfig::load(cli.config.as_deref())?);
+
+    match cli.command {
+        Some(Command::Estimate) => {
+            println!("Estimated image mirror: {}", estimate(&config).await?);
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Inspect { name }) => {
+            println!("{}", inspect(&config, &name).await?);
+            return Ok(ExitCode::SUCCESS);
         }
+        #[cfg(feature = "serve")]
+        Some(Command::Serve { address }) => {
+            let addr = address
+                .or_else(|| env::var("SERVE_ADDRESS").ok())
+                .unwrap_or("127.0.0.1:8080".to_string());
+            warframe_exports::serve(config, &addr).await?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        None if cli.dry_run => {
+            println!("Estimated image mirror: {}", estimate(&config).await?);
+            return Ok(ExitCode::SUCCESS);
+        }
+        None => {}
     }
 
-    // Wait for all downloads to finish...
-    export_set.join_all().await;
-
-    if updated_hash {
-        let json = serde_json::to_string(&*export_hashes.lock().await)?;
-        println!("Saved export hashes ➞ {}", export_hash_location);
-        fs::write(&export_hash_location, json).await?;
-
-        if updated_manifest {
-            let mut image_set = JoinSet::new();
-            let mut image_hashes: Arc<Mutex<BTreeMap<String, String>>> = Arc::new(Mutex::new(
-                load_hash_map_from_file(&image_hash_location).await?,
-            ));
-
-            let export_manifest: ExportManifest = serde_json::from_str(
-                &fs::read_to_string(format!("{}/{}", &storage_folders[2], "ExportManifest.json"))
-                    .await?,
-            )?;
-
-            for ExportManifestItem {
-                texture_location,
-                unique_name,
-            } in export_manifest.Manifest
-            {
-                let resource = split_string_to_resource(&texture_location)?;
-
-                check_and_download_resource(
-                    &client,
-                    &mut image_hashes,
-                    &mut image_set,
-                    Arc::new(Resource {
-                        name: unique_name.clone(),
-                        hash: resource.hash,
-                    }),
-                    Arc::new(DownloadConfig {
-                        url: format!(
-                            "{}{}{}",
-                            WARFRAME_CONTENT_URL, PUBLIC_EXPORT_PATH, &texture_location
-                        ),
-                        path: storage_folders[1].clone(),
-                        name: format!("{}.png", &unique_name.replace("/", ".")[1..]),
-                        as_text: false,
-                    }),
-                )
-                .await?;
+    // Kept alive for the duration of the run, so orchestrators can probe it.
+    #[cfg(feature = "serve")]
+    let health = config.health_port.map(|port| {
+        let status = Arc::new(RwLock::new(HealthStatus::default()));
+        let server_status = Arc::clone(&status);
+        tokio::spawn(async move {
+            if let Err(err) = serve_health(port, server_status).await {
+                println!("Health server failed: {}", err);
             }
+        });
+        status
+    });
 
-            // Wait for all downloads to finish...
-            image_set.join_all().await;
+    // Registered before the first run, so a signal received mid-run cancels it and its progress is saved.
+    let mut shutdown = pin!(shutdown_signal()?);
 
-            let json = serde_json::to_string(&*image_hashes.lock().await)?;
-            println!("Saved image hashes ➞ {}", &image_hash_location);
-            fs::write(&image_hash_location, json).await?;
-        } else {
-            println!("No changes found in export manifest!")
+    let Some(loop_interval) = config.loop_interval else {
+        let (result, interrupted) = run(
+            config,
+            shutdown,
+            #[cfg(feature = "serve")]
+            health.as_ref(),
+        )
+        .await;
+        let report = match result {
+            Err(err) if err.is::<DiskFull>() => {
+                println!("{}", err);
+                return Ok(ExitCode::from(EXIT_DISK_FULL));
+            }
+            result => result?,
+        };
+        if interrupted {
+            return Ok(ExitCode::from(EXIT_INTERRUPTED));
         }
-    } else {
-        println!("No exports to update!");
-    }
 
-    Ok(())
-}
-
-/// Downloads the export index and decompresses it using LZMA.
-///
-/// # Arguments
-/// - `client`: A reference to the HTTP client used for making requests.
-///
-/// # Returns
-/// A `Result` containing the decompressed export index as a `String`, or an error.
-async fn download_export_index(client: &ClientWithMiddleware) -> Result<String, Box<dyn Error>> {
-    let origin_url = env::var("WARFRAME_ORIGIN_URL").unwrap_or(WARFRAME_ORIGIN_URL.to_string());
-    let lzma_url = format!("{}{}", origin_url, LZMA_URL_PATH);
-
-    let response = client
-        .get(Url::parse(&lzma_url)?)
-        .header(
-            "X-Proxy-Token",
-            env::var("X_PROXY_TOKEN").unwrap_or_default(),
+        return match report.outcome {
+            SyncOutcome::Updated => Ok(ExitCode::SUCCESS),
+            SyncOutcome::Unchanged => Ok(ExitCode::from(EXIT_UNCHANGED)),
+            SyncOutcome::TimedOut => Ok(ExitCode::from(EXIT_TIMED_OUT)),
+        };
+    };
+
+    let mut cycle = 1;
+    loop {
+        let (result, interrupted) = run(
+            config.clone(),
+            shutdown.as_mut(),
+            #[cfg(feature = "serve")]
+            health.as_ref(),
         )
-        .send()
-        .await?;
+        .await;
+        match result {
+            Ok(report) => println!(
+                "Cycle {} finished: {} added, {} updated, {} failed",
+                cycle,
+                report.added.len(),
+                report.updated.len(),
+                report.failed.len()
+            ),
+            // Later cycles would only fail the same way, so the process exits as a single run would.
+            Err(err) if err.is::<DiskFull>() => {
+                println!("Cycle {} failed: {}", cycle, err);
+                return Ok(ExitCode::from(EXIT_DISK_FULL));
+            }
+            Err(err) => println!("Cycle {} failed: {}", cycle, err),
+        }
+        if interrupted {
+            println!("Shutting down");
+            return Ok(ExitCode::SUCCESS);
+        }
+        println!("Next run in {}s", loop_interval.as_secs());
 
-    if !response.status().is_success() {
-        return Err(format!(
-            "Failed to download export index: {}",
-            response.status()
-        )
-        .into());
+        tokio::select! {
+            _ = sleep(loop_interval) => cycle += 1,
+            _ = &mut shutdown => {
+                println!("Shutting down");
+                return Ok(ExitCode::SUCCESS);
+            }
+        }
     }
-
-    let bytes = response.bytes().await?;
-    let cursor = Cursor::new(bytes);
-
-    let mut reader = BufReader::new(cursor);
-    let mut decomp: Vec<u8> = Vec::new();
-    lzma_rs::lzma_decompress(&mut reader, &mut decomp)?;
-    let out = std::str::from_utf8(&decomp)?;
-
-    Ok(out.to_string())
 }
 
-/// Checks if a resource should be downloaded by comparing its hash and initiates the download if necessary.
+/// Runs a single sync, recording its result for the health endpoints.
+/// Once a shutdown is requested, the sync is cancelled and waited for, so its progress is saved.
 ///
 /// # Arguments
-/// - `client`: Shared HTTP client for making requests.
-/// - `hashes`: Shared hash map containing resource hashes.
-/// - `join_set`: A set of asynchronous tasks for parallel downloads.
-/// - `resource`: Resource descriptor string containing the name and hash.
-/// - `download_config`: Struct that specifies the download configuration.
+/// - `config`: The configuration for this run.
+/// - `shutdown`: Completes once a shutdown is requested. Not polled again once it has completed.
+/// - `health`: The status served by `HEALTH_PORT`, if enabled.
 ///
 /// # Returns
-/// - A tuple `(hash_updated, is_manifest)` indicating if the hash was updated and if the resource is a manifest.
-async fn check_and_download_resource(
-    client: &Arc<ClientWithMiddleware>,
-    hashes: &Arc<Mutex<BTreeMap<String, String>>>,
-    join_set: &mut JoinSet<()>,
-    resource: Arc<Resource>,
-    download_config: Arc<DownloadConfig>,
-) -> Result<(bool, bool), Box<dyn Error>> {
-    let hash_lock = hashes.lock().await;
-    let existing_resource = hash_lock.get(&resource.name).unwrap_or(&UNWRAP_NONE);
-    let is_manifest = resource.name == "ExportManifest.json";
-
-    // Matching resource was found, caller should continue.
-    if *existing_resource == resource.hash {
-        return Ok((false, is_manifest));
-    }
-
-    // Got None, meaning a new resource.
-    if *existing_resource == *UNWRAP_NONE {
-        println!(
-            "Added a new resource ➞ {} ({})",
-            resource.name, resource.hash
-        );
-    } else {
-        // An updated resource was found.
-        println!(
-            "Updated an existing resource ➞ {} ({} from {})",
-            resource.name, resource.hash, existing_resource
-        );
-    }
-
-    // Frees the lock on hashes
-    drop(hash_lock);
-
-    let client = Arc::clone(client);
-    let hashes = Arc::clone(hashes);
-    let download_config = Arc::clone(&download_config);
-    join_set.spawn(async move {
-        let result = download_file(&client, download_config).await;
-        match result.map_err(|e| e.to_string()) {
-            Ok(..) => {
-                hashes
-                    .lock()
-                    .await
-                    .insert(resource.name.to_owned(), resource.hash.to_owned());
-                ()
-            }
-            Err(err) => println!(
-                "An issue occurred while downloading {} ({}): {}",
-                resource.name, resource.hash, err
-            ),
+/// - The result of the run, and whether a shutdown was requested during it.
+async fn run(
+    config: SyncConfig,
+    shutdown: Pin<&mut impl Future<Output = ()>>,
+    #[cfg(feature = "serve")] health: Option<&Arc<RwLock<HealthStatus>>>,
+) -> (Result<RunReport, Box<dyn Error>>, bool) {
+    let (cancel, cancelled) = watch::channel(false);
+    let mut sync = pin!(run_sync(config, Box::new(FileSystemSink), cancelled));
+    let mut interrupted = false;
+    let result = tokio::select! {
+        result = &mut sync => result,
+        _ = shutdown => {
+            println!("Shutting down once progress is saved");
+            interrupted = true;
+            cancel.send_replace(true);
+            sync.await
         }
-    });
+    };
+    #[cfg(feature = "serve")]
+    if let Some(health) = health {
+        health.write().await.record(&result);
+    }
 
-    Ok((true, is_manifest))
+    (result, interrupted)
 }
 
-/// Downloads a file from a given URL and saves it to a specified path.
-/// Optionally processes the content as text by sanitizing newlines.
-///
-/// # Arguments
-/// - `client`: HTTP client for making the request.
-/// - `download_config`: Struct that specifies the download configuration.
+/// Listens for the signals that request a graceful shutdown: `SIGINT` and, on Unix, `SIGTERM`.
 ///
 /// # Returns
-/// - `Ok(())` if the file is downloaded and saved successfully.
-async fn download_file(
-    client: &ClientWithMiddleware,
-    download_config: Arc<DownloadConfig>,
-) -> Result<(), Box<dyn Error>> {
-    let response = client.get(Url::parse(&download_config.url)?).send().await?;
-
-    if !response.status().is_success() {
-        return Err(format!(
-            "Failed to download {}: {}",
-            download_config.name,
-            response.status()
-        )
-        .into());
-    }
-
-    if download_config.as_text {
-        let content = response.text().await?;
-        let sanitized = RE_ESCAPES.replace_all(&content, escape_match).to_string();
-        let parsed_json: serde_json::Value = serde_json::from_str(&sanitized)?;
-
-        fs::write(
-            format!(
-                "{}/{}.min.json",
-                &download_config.path, &download_config.name
-            ),
-            serde_json::to_string(&parsed_json)?,
-        )
-        .await?;
-        fs::write(
-            format!("{}/{}.json", &download_config.path, &download_config.name),
-            serde_json::to_string_pretty(&parsed_json)?,
-        )
-        .await?;
-
-        println!("[DOWNLOADED] ➞ {}", download_config.name);
-    } else {
-        let content = response.bytes().await?;
-        let reader = ImageReader::new(Cursor::new(&content)).with_guessed_format()?;
-
-        if let Ok(decoded) = reader.decode() {
-            let rgba_image = decoded.to_rgba8();
-            let (width, height) = rgba_image.dimensions();
-
-            let raw_image =
-                Image::from_vec_u8(width, height, rgba_image.into_raw(), PixelType::U8x4)?;
-
-            // Save the original image, but constrain to 512x512.
-            //  Some are originally over this size, while some are originally under.
-            let original_path = format!("{}/{}", &download_config.path, &download_config.name);
-            if width == 512 && height == 512 {
-                fs::write(&original_path, &content).await?;
-            } else {
-                let resized_buf = resize_image(&raw_image, 512).await?;
-                fs::write(&original_path, resized_buf).await?;
-            }
-
-            for size in IMAGE_SIZES {
-                let resized_buf = resize_image(&raw_image, *size).await?;
-                fs::write(
-                    format!(
-                        "{}/{}x{}/{}",
-                        &download_config.path, size, size, &download_config.name
-                    ),
-                    resized_buf,
-                )
-                .await?;
-            }
-
-            println!("[DOWNLOADED] ➞ {}", download_config.name);
-        } else {
-            return Err("Invalid or corrupt image format".into());
+/// - A future that completes once either signal is received.
+fn shutdown_signal() -> Result<impl Future<Output = ()>, Box<dyn Error>> {
+    // Both are registered here rather than when first polled, so a signal received before then isn't missed.
+    #[cfg(unix)]
+    let mut interrupt = signal::unix::signal(signal::unix::SignalKind::interrupt())?;
+    #[cfg(unix)]
+    let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+    #[cfg(not(unix))]
+    let interrupt = signal::ctrl_c();
+
+    Ok(async move {
+        #[cfg(unix)]
+        tokio::select! {
+            _ = interrupt.recv() => {},
+            _ = terminate.recv() => {},
         }
-    }
-
-    Ok(())
+        #[cfg(not(unix))]
+        interrupt.await.ok();
+    })
 }
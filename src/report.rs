@@ -0,0 +1,324 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The outcome of a sync run.
+/// - `Updated`: Every phase ran to completion, and at least one resource changed.
+/// - `Unchanged`: Every phase ran to completion, and nothing changed.
+/// - `TimedOut`: The maximum runtime was exceeded or the run was cancelled; remaining work was skipped and progress saved.
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncOutcome {
+    Updated,
+    #[default]
+    Unchanged,
+    TimedOut,
+}
+
+/// Why a resource was not downloaded.
+/// - `Unchanged`: The stored hash matches the latest hash.
+/// - `DisallowedFormat`: The source image format is not in `ALLOWED_IMAGE_FORMATS`.
+/// - `NotFound`: The content server responded with `404 Not Found`.
+/// - `NotModified`: The content server responded with `304 Not Modified` to the stored ETag.
+/// - `Placeholder`: The image is a blank placeholder, and `PLACEHOLDERS` keeps those out of the mirror.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    Unchanged,
+    DisallowedFormat,
+    NotFound,
+    NotModified,
+    Placeholder,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Unchanged => write!(f, "unchanged"),
+            SkipReason::DisallowedFormat => write!(f, "disallowed format"),
+            SkipReason::NotFound => write!(f, "not found"),
+            SkipReason::NotModified => write!(f, "not modified"),
+            SkipReason::Placeholder => write!(f, "placeholder"),
+        }
+    }
+}
+
+/// Struct that holds a resource that was skipped.
+/// - `name`: The name of the resource.
+/// - `reason`: Why the resource was skipped.
+#[derive(Serialize, Debug, Clone)]
+pub struct SkippedResource {
+    pub name: String,
+    pub reason: SkipReason,
+}
+
+/// Struct that holds a resource that failed to download or process.
+/// - `name`: The name of the resource.
+/// - `hash`: The hash of the resource that was attempted.
+/// - `error`: A description of the error.
+#[derive(Serialize, Debug, Clone)]
+pub struct FailedResource {
+    pub name: String,
+    pub hash: String,
+    pub error: String,
+}
+
+/// Struct that holds a resource whose URL redirected elsewhere.
+/// - `name`: The name of the resource.
+/// - `url`: The URL that was requested.
+/// - `final_url`: The URL the content was served from.
+#[derive(Serialize, Debug, Clone)]
+pub struct RedirectedResource {
+    pub name: String,
+    pub url: String,
+    pub final_url: String,
+}
+
+/// Struct that holds the accounting of a sync run, saved as `run_report.json`.
+/// - `outcome`: The outcome of the run.
+/// - `started_at`: When the run started, in seconds since the Unix epoch.
+/// - `duration_secs`: How long the run took, in seconds.
+/// - `added`: Resources that were downloaded for the first time.
+/// - `updated`: Resources that were downloaded again because their hash changed.
+/// - `skipped`: Resources that were not downloaded, and why.
+/// - `failed`: Resources that failed, and the error.
+/// - `redirected`: Resources whose URL redirected, and where to.
+/// - `index_reordered`: Whether the export index listed resources in a different order than the last run,
+///   if `track_index_order` is enabled.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct RunReport {
+    pub outcome: SyncOutcome,
+    pub started_at: u64,
+    pub duration_secs: f64,
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub skipped: Vec<SkippedResource>,
+    pub failed: Vec<FailedResource>,
+    pub redirected: Vec<RedirectedResource>,
+    pub index_reordered: bool,
+}
+
+impl RunReport {
+    /// Records a skipped resource.
+    pub fn skip(&mut self, name: &str, reason: SkipReason) {
+        self.skipped.push(SkippedResource {
+            name: name.to_string(),
+            reason,
+        });
+    }
+
+    /// Records a failed resource.
+    pub fn fail(&mut self, name: &str, hash: &str, error: &str) {
+        self.failed.push(FailedResource {
+            name: name.to_string(),
+            hash: hash.to_string(),
+            error: error.to_string(),
+        });
+    }
+
+    /// Whether the run completed without failures or running out of time.
+    pub fn succeeded(&self) -> bool {
+        self.failed.is_empty() && self.outcome != SyncOutcome::TimedOut
+    }
+
+    /// Renders the report as metrics in the Prometheus text exposition format.
+    ///
+    /// # Arguments
+    /// - `last_success`: When the last successful run finished, in seconds since the Unix epoch.
+    ///
+    /// # Returns
+    /// - The metrics, ready to be written for a node_exporter textfile collector.
+    pub fn to_prometheus(&self, last_success: Option<u64>) -> String {
+        let mut metrics = String::new();
+
+        metrics.push_str(
+            "# HELP warframe_export_resources_total Resources processed in the last run, by state.\n",
+        );
+        metrics.push_str("# TYPE warframe_export_resources_total gauge\n");
+        for (state, count) in [
+            ("added", self.added.len()),
+            ("updated", self.updated.len()),
+            ("skipped", self.skipped.len()),
+            ("failed", self.failed.len()),
+        ] {
+            metrics.push_str(&format!(
+                "warframe_export_resources_total{{state=\"{}\"}} {}\n",
+                state, count
+            ));
+        }
+
+        metrics.push_str(
+            "# HELP warframe_export_updated_total Resources added or updated in the last run.\n",
+        );
+        metrics.push_str("# TYPE warframe_export_updated_total gauge\n");
+        metrics.push_str(&format!(
+            "warframe_export_updated_total {}\n",
+            self.added.len() + self.updated.len()
+        ));
+
+        metrics
+            .push_str("# HELP warframe_export_run_duration_seconds How long the last run took.\n");
+        metrics.push_str("# TYPE warframe_export_run_duration_seconds gauge\n");
+        metrics.push_str(&format!(
+            "warframe_export_run_duration_seconds {}\n",
+            self.duration_secs
+        ));
+
+        if let Some(last_success) = last_success {
+            metrics.push_str(
+                "# HELP warframe_export_last_success_timestamp When the last successful run finished.\n",
+            );
+            metrics.push_str("# TYPE warframe_export_last_success_timestamp gauge\n");
+            metrics.push_str(&format!(
+                "warframe_export_last_success_timestamp {}\n",
+                last_success
+            ));
+        }
+
+        metrics
+    }
+
+    /// Merges the report of one language into a combined report, prefixing resource names with the language.
+    ///
+    /// # Arguments
+    /// - `language`: The language the report is for.
+    /// - `other`: The report of that language.
+    pub fn merge(&mut self, language: &str, other: RunReport) {
+        self.outcome = match (self.outcome, other.outcome) {
+            (SyncOutcome::TimedOut, _) | (_, SyncOutcome::TimedOut) => SyncOutcome::TimedOut,
+            (SyncOutcome::Updated, _) | (_, SyncOutcome::Updated) => SyncOutcome::Updated,
+            _ => SyncOutcome::Unchanged,
+        };
+
+        self.index_reordered |= other.index_reordered;
+
+        let prefix = |name: String| format!("{}/{}", language, name);
+        self.added.extend(other.added.into_iter().map(prefix));
+        self.updated.extend(other.updated.into_iter().map(prefix));
+        self.skipped
+            .extend(other.skipped.into_iter().map(|skipped| SkippedResource {
+                name: prefix(skipped.name),
+                ..skipped
+            }));
+        self.failed
+            .extend(other.failed.into_iter().map(|failed| FailedResource {
+                name: prefix(failed.name),
+                ..failed
+            }));
+        self.redirected.extend(
+            other
+                .redirected
+                .into_iter()
+                .map(|redirected| RedirectedResource {
+                    name: prefix(redirected.name),
+                    ..redirected
+                }),
+        );
+    }
+
+    /// Renders the added and updated resources as a dated Markdown section, for a changelog.
+    ///
+    /// # Arguments
+    /// - `names`: Human-readable names, keyed by unique name, for the resources that have one.
+    ///
+    /// # Returns
+    /// - The section, headed by the UTC start time of the run.
+    pub fn to_changelog(&self, names: &BTreeMap<String, String>) -> String {
+        let mut changelog = format!("## {}\n", format_utc(self.started_at));
+        for (heading, resources) in [("Added", &self.added), ("Updated", &self.updated)] {
+            if resources.is_empty() {
+                continue;
+            }
+            changelog.push_str(&format!("\n### {} ({})\n\n", heading, resources.len()));
+            for resource in resources {
+                match names.get(resource) {
+                    Some(name) => changelog.push_str(&format!("- {} (`{}`)\n", name, resource)),
+                    None => changelog.push_str(&format!("- `{}`\n", resource)),
+                }
+            }
+        }
+        changelog
+    }
+
+    /// Records a resource whose URL redirected.
+    pub fn redirect(&mut self, name: &str, url: &str, final_url: &str) {
+        self.redirected.push(RedirectedResource {
+            name: name.to_string(),
+            url: url.to_string(),
+            final_url: final_url.to_string(),
+        });
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut skip_counts: BTreeMap<SkipReason, usize> = BTreeMap::new();
+        for skipped in &self.skipped {
+            *skip_counts.entry(skipped.reason).or_default() += 1;
+        }
+
+        write!(
+            f,
+            "{} added, {} updated, {} skipped",
+            self.added.len(),
+            self.updated.len(),
+            self.skipped.len()
+        )?;
+        if !skip_counts.is_empty() {
+            let counts: Vec<String> = skip_counts
+                .iter()
+                .map(|(reason, count)| format!("{} {}", count, reason))
+                .collect();
+            write!(f, " ({})", counts.join(", "))?;
+        }
+        write!(f, ", {} failed", self.failed.len())?;
+
+        for failed in &self.failed {
+            write!(
+                f,
+                "\n  [FAILED] {} ({}): {}",
+                failed.name, failed.hash, failed.error
+            )?;
+        }
+
+        for redirected in &self.redirected {
+            write!(
+                f,
+                "\n  [REDIRECTED] {}: {} ➞ {}",
+                redirected.name, redirected.url, redirected.final_url
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Formats a time as a UTC date and time, e.g. `2025-01-31 18:04 UTC`.
+///
+/// # Arguments
+/// - `secs`: The time, in seconds since the Unix epoch.
+fn format_utc(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let (hour, minute) = (secs % 86_400 / 3_600, secs % 3_600 / 60);
+
+    // Converts days since the epoch to a proleptic Gregorian date, in eras of 400 years.
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        year, month, day, hour, minute
+    )
+}
@@ -0,0 +1,268 @@
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use fast_image_resize::images::Image;
+use fast_image_resize::PixelType;
+use serde::Deserialize;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::fs;
+use tokio::net::TcpListener;
+use tokio::sync::RwLock;
+
+use crate::{resize_image, OutputFormat, RunReport, SyncConfig, IMAGE_SIZES};
+
+/// Struct that holds the status reported by the health endpoints.
+/// - `last_run`: When the last run finished, in seconds since the Unix epoch.
+/// - `last_success`: When the last successful run finished, in seconds since the Unix epoch.
+/// - `last_error`: The error the last run failed with, if it failed.
+/// - `last_report`: The report of the last run that completed, if any.
+#[derive(Default)]
+pub struct HealthStatus {
+    pub last_run: Option<u64>,
+    pub last_success: Option<u64>,
+    pub last_error: Option<String>,
+    pub last_report: Option<RunReport>,
+}
+
+impl HealthStatus {
+    /// Records the result of a finished run.
+    pub fn record(&mut self, result: &Result<RunReport, Box<dyn Error>>) {
+        let finished_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .ok();
+        self.last_run = finished_at;
+
+        match result {
+            Ok(report) => {
+                if report.succeeded() {
+                    self.last_success = finished_at;
+                }
+                self.last_error = None;
+                self.last_report = Some(report.clone());
+            }
+            Err(err) => self.last_error = Some(err.to_string()),
+        }
+    }
+}
+
+/// Struct that holds the query parameters of an image request.
+/// - `size`: The requested size; the 512x512 original is served if omitted.
+#[derive(Deserialize)]
+struct ImageQuery {
+    size: Option<u32>,
+}
+
+/// Serves images over HTTP, generating resized variants on demand.
+///
+/// Requests to `/image/{name}?size=64` are answered from `{image_dir}/64x64/` if the variant exists;
+/// otherwise it is resized from the original, cached to disk, and returned. Only sizes listed in
+/// `IMAGE_SIZES` are accepted.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the image directory and output formats.
+/// - `addr`: The address to listen on, e.g. `127.0.0.1:8080`.
+///
+/// # Returns
+/// - Only returns if the server fails.
+pub async fn serve(config: SyncConfig, addr: &str) -> Result<(), Box<dyn Error>> {
+    config.check_size_dirs()?;
+
+    let app = Router::new()
+        .route("/image/{name}", get(get_image))
+        .with_state(Arc::new(config));
+
+    let listener = TcpListener::bind(addr).await?;
+    println!("Serving images on http://{}", addr);
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+/// Serves liveness and metrics endpoints for orchestrators, such as Kubernetes probes.
+///
+/// `/healthz` always answers `200 OK` while the process is alive, with the last run's status and
+/// timestamp as JSON. `/metrics` answers with the last run's metrics in the Prometheus text format.
+///
+/// # Arguments
+/// - `port`: The port to listen on, on all interfaces.
+/// - `status`: The status to report, updated as runs finish.
+///
+/// # Returns
+/// - Only returns if the server fails.
+pub async fn serve_health(
+    port: u16,
+    status: Arc<RwLock<HealthStatus>>,
+) -> Result<(), Box<dyn Error>> {
+    let app = Router::new()
+        .route("/healthz", get(get_health))
+        .route("/metrics", get(get_metrics))
+        .with_state(status);
+
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    println!("Serving health checks on http://0.0.0.0:{}", port);
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+/// Handles a liveness request.
+async fn get_health(State(status): State<Arc<RwLock<HealthStatus>>>) -> Response {
+    let status = status.read().await;
+    let last_status = match (&status.last_error, &status.last_report) {
+        (Some(..), _) => Some("failed"),
+        (None, Some(report)) if report.succeeded() => Some("succeeded"),
+        (None, Some(..)) => Some("incomplete"),
+        (None, None) => None,
+    };
+
+    let health = serde_json::json!({
+        "status": "ok",
+        "last_run": status.last_run,
+        "last_status": last_status,
+        "last_success": status.last_success,
+        "last_error": status.last_error,
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        health.to_string(),
+    )
+        .into_response()
+}
+
+/// Handles a metrics request.
+async fn get_metrics(State(status): State<Arc<RwLock<HealthStatus>>>) -> Response {
+    let status = status.read().await;
+    let metrics = status
+        .last_report
+        .as_ref()
+        .map(|report| report.to_prometheus(status.last_success))
+        .unwrap_or_default();
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics,
+    )
+        .into_response()
+}
+
+/// Handles an image request.
+async fn get_image(
+    State(config): State<Arc<SyncConfig>>,
+    Path(name): Path<String>,
+    Query(query): Query<ImageQuery>,
+) -> Response {
+    if name.starts_with('.') || name.contains(['/', '\\']) {
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    let size = match query.size {
+        None | Some(512) => None,
+        Some(size) if IMAGE_SIZES.contains(&size) => Some(size),
+        Some(..) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+
+    match load_image(&config, &name, size).await {
+        Ok(Some((bytes, format))) => {
+            let content_type = match format {
+                OutputFormat::Png => "image/png",
+                OutputFormat::WebP => "image/webp",
+            };
+            ([(header::CONTENT_TYPE, content_type)], bytes).into_response()
+        }
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            println!("An issue occurred while serving {}: {}", name, err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Reads an image from disk, resizing and caching the requested variant if it doesn't exist yet.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the image directory and output formats.
+/// - `name`: The file name of the original image, e.g. `Lotus.Interface.Icons.Foo.png`.
+/// - `size`: The requested size, or `None` for the original.
+///
+/// # Returns
+/// - `Some((bytes, format))` with the encoded image, or `None` if the original doesn't exist.
+async fn load_image(
+    config: &SyncConfig,
+    name: &str,
+    size: Option<u32>,
+) -> Result<Option<(Vec<u8>, OutputFormat)>, Box<dyn Error>> {
+    let image_dir = config.image_dir();
+    let original_path = format!("{}/{}", image_dir, name);
+    let Some((stem, extension)) = name.rsplit_once('.') else {
+        return Ok(None);
+    };
+    let Ok(original_format) = extension.parse::<OutputFormat>() else {
+        return Ok(None);
+    };
+
+    let Some(size) = size else {
+        return Ok(fs::read(&original_path)
+            .await
+            .ok()
+            .map(|bytes| (bytes, original_format)));
+    };
+
+    let format = config.format_for(size);
+    let variant_path = format!(
+        "{}/{}/{}.{}",
+        image_dir,
+        config.size_dir(size),
+        stem,
+        format.extension()
+    );
+    if let Ok(bytes) = fs::read(&variant_path).await {
+        return Ok(Some((bytes, format)));
+    }
+
+    let Ok(original) = fs::read(&original_path).await else {
+        return Ok(None);
+    };
+
+    let decoded = image::load_from_memory(&original)?;
+    let (width, height) = (decoded.width(), decoded.height());
+    // Originals stored as grayscale keep their color type, so their variants are grayscale too.
+    let raw_image = match config.flatten_background {
+        Some(..) if config.optimize_grayscale && !decoded.color().has_color() => {
+            Image::from_vec_u8(width, height, decoded.to_luma8().into_raw(), PixelType::U8)?
+        }
+        None if config.optimize_grayscale && !decoded.color().has_color() => Image::from_vec_u8(
+            width,
+            height,
+            decoded.to_luma_alpha8().into_raw(),
+            PixelType::U8x2,
+        )?,
+        Some(..) => {
+            Image::from_vec_u8(width, height, decoded.to_rgb8().into_raw(), PixelType::U8x3)?
+        }
+        None => Image::from_vec_u8(
+            width,
+            height,
+            decoded.to_rgba8().into_raw(),
+            PixelType::U8x4,
+        )?,
+    };
+
+    let resized_buf = resize_image(
+        &raw_image,
+        size,
+        format,
+        config.filter_for(size),
+        config.sharpen_for(size),
+    )
+    .await?;
+    fs::create_dir_all(format!("{}/{}", image_dir, config.size_dir(size))).await?;
+    fs::write(&variant_path, &resized_buf).await?;
+
+    Ok(Some((resized_buf, format)))
+}
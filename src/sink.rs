@@ -0,0 +1,78 @@
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::fs;
+
+/// The future returned by `OutputSink::write`.
+pub type SinkFuture<'a> = Pin<Box<dyn Future<Output = io::Result<()>> + Send + 'a>>;
+
+/// A destination for the exports and images written by a sync run.
+///
+/// Outputs are addressed by the path they would have on disk, e.g. `./output/image/Lotus.Foo.png`,
+/// so a sink can map them to object keys, keep them in memory, or write them to several places.
+/// State kept between runs, such as hash files and the export sources `download_file` caches with
+/// `write_source`, is always written to disk rather than through the sink, and `VERIFY_OUTPUTS` reads
+/// images back from disk, so it needs a sink that writes there too.
+pub trait OutputSink: Send + Sync {
+    /// Writes an output, replacing any previous content at the same path.
+    ///
+    /// # Arguments
+    /// - `path`: The path of the output, within the configured output directories.
+    /// - `bytes`: The content of the output.
+    ///
+    /// # Returns
+    /// - `Ok(())` once the output has been written.
+    fn write<'a>(&'a self, path: &'a str, bytes: &'a [u8]) -> SinkFuture<'a>;
+}
+
+/// The default sink, which writes outputs to the local file system.
+pub struct FileSystemSink;
+
+impl OutputSink for FileSystemSink {
+    fn write<'a>(&'a self, path: &'a str, bytes: &'a [u8]) -> SinkFuture<'a> {
+        Box::pin(async move {
+            // A failed write, e.g. on a full disk, may leave a truncated file behind.
+            if let Err(err) = fs::write(path, bytes).await {
+                fs::remove_file(path).await.ok();
+                return Err(err);
+            }
+            Ok(())
+        })
+    }
+}
+
+/// A sink that also copies every output into a delta directory, so the changes of a run can be shipped on their own.
+/// - `inner`: The sink outputs are written to first.
+/// - `roots`: The output directories, each with the path its outputs are copied to within the delta directory.
+///   The first matching root is used, and outputs outside all of them aren't copied.
+pub(crate) struct DeltaSink {
+    pub(crate) inner: Arc<dyn OutputSink>,
+    pub(crate) roots: Vec<(String, String)>,
+}
+
+impl DeltaSink {
+    /// The path an output is copied to, if it is within one of the `roots`.
+    fn delta_path(&self, path: &str) -> Option<String> {
+        self.roots.iter().find_map(|(root, delta_root)| {
+            let relative = path.strip_prefix(root.as_str())?.trim_start_matches('/');
+            Some(format!("{}/{}", delta_root, relative))
+        })
+    }
+}
+
+impl OutputSink for DeltaSink {
+    fn write<'a>(&'a self, path: &'a str, bytes: &'a [u8]) -> SinkFuture<'a> {
+        Box::pin(async move {
+            self.inner.write(path, bytes).await?;
+            if let Some(delta_path) = self.delta_path(path) {
+                if let Some(parent) = Path::new(&delta_path).parent() {
+                    fs::create_dir_all(parent).await?;
+                }
+                fs::write(&delta_path, bytes).await?;
+            }
+            Ok(())
+        })
+    }
+}
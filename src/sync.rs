@@ -0,0 +1,3887 @@
+use fast_image_resize::images::Image;
+use fast_image_resize::PixelType;
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, DynamicImage, GenericImageView, ImageFormat, ImageReader};
+use reqwest::header::{CONTENT_LENGTH, ETAG, IF_NONE_MATCH};
+use reqwest::redirect::Policy;
+use reqwest::StatusCode;
+use reqwest::{Client, Response};
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+use reqwest_retry::{
+    default_on_request_failure, policies::ExponentialBackoff, RetryError, RetryTransientMiddleware,
+    Retryable, RetryableStrategy,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufReader, Cursor, ErrorKind};
+use std::path::Path;
+use std::pin::pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs;
+use tokio::sync::{watch, Mutex, OnceCell, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::{sleep, sleep_until, timeout, Instant};
+
+use crate::log::{finish_run_log, log, start_run_log};
+use crate::sink::DeltaSink;
+use crate::urls::{index_url, manifest_url, texture_url};
+use crate::{
+    apply_filter, dhash, diff_hash_maps, encode_hash_map, escape_match, flatten_background,
+    index_entries, index_order_changed, load_etag_map_from_file, load_hash_map_from_file,
+    load_version_from_file, parse_export_manifest, resize_image, resize_image_blocking,
+    resource_kind, split_string_to_resource, to_ndjson, transform_keys, unwrap_export, Compression,
+    DownloadConfig, ExportHash, ExportManifest, ExportManifestItem, HashFormat, ImageHash,
+    OutputFormat, OutputSink, PlaceholderMode, Resource, RunReport, Schedule, SkipReason,
+    SyncConfig, SyncEvent, SyncOutcome, SyncPhase, TlsErrorMode, VersionInfo, IMAGE_SIZES,
+    RE_ESCAPES,
+};
+
+/// The outcome of fetching the export index.
+/// - `Unchanged`: The server reported the same ETag as the last processed index.
+/// - `Updated`: The decompressed index content, along with its ETag if one was provided.
+pub(crate) enum ExportIndex {
+    Unchanged,
+    Updated {
+        content: String,
+        etag: Option<String>,
+    },
+}
+
+/// How long in-flight downloads may keep running once the maximum runtime has been exceeded or the run cancelled.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+/// How many redirects a single request may follow before failing.
+const MAX_REDIRECTS: usize = 5;
+
+/// How long to wait before the first retry of an export index download that couldn't connect, doubled for each one after.
+const CONNECT_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// The error for a successful response without a body, which some proxies return when they fail.
+/// Responses that declare an empty body are retried first, so this is only returned once retries are exhausted.
+/// - `url`: The URL that was requested.
+#[derive(Debug)]
+struct EmptyResponse {
+    url: String,
+}
+
+impl fmt::Display for EmptyResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "EmptyResponse: {} returned a success status with an empty body, likely from a failing proxy",
+            self.url
+        )
+    }
+}
+
+impl Error for EmptyResponse {}
+
+/// The error a run stops with once the output volume is full.
+/// - `message`: The underlying I/O error.
+#[derive(Debug)]
+pub struct DiskFull {
+    pub message: String,
+}
+
+impl fmt::Display for DiskFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Disk full: {} (free up space on the output volume, then run again to resume)",
+            self.message
+        )
+    }
+}
+
+impl Error for DiskFull {}
+
+/// Whether an error, or any error it was caused by, is from the output volume being full.
+fn is_disk_full(err: &(dyn Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        let storage_full = err.downcast_ref::<io::Error>().is_some_and(|err| {
+            matches!(
+                err.kind(),
+                ErrorKind::StorageFull | ErrorKind::QuotaExceeded
+            )
+        });
+        if storage_full || err.is::<DiskFull>() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Struct that holds the state shared between all tasks of a run.
+/// - `client`: The HTTP client to share between all requests.
+/// - `config`: The configuration for this run.
+/// - `sink`: Where exports and images are written to.
+/// - `report`: The accounting of the run so far.
+/// - `content_hashes`: BLAKE3 hashes of the images saved this run, keyed by resource name.
+/// - `perceptual_hashes`: dHashes of the images saved this run, keyed by resource name, if enabled.
+/// - `dimensions`: Dimensions of the originals saved this run, keyed by resource name, if enabled.
+/// - `output_hashes`: BLAKE3 hashes of the image outputs written this run, keyed by resource name and `output_key`.
+/// - `last_flush`: How many downloads completed since the hash map was last saved, and when that was.
+/// - `failures`: How many downloads have failed so far, counted by the download tasks.
+/// - `disk_full`: The error of the first download that failed because the output volume is full, if any.
+/// - `shared_textures`: Downloads of textures referenced by several manifest items, keyed by URL,
+///   with how many of those items haven't fetched it yet.
+/// - `download_permits`: Bounds the downloads in flight, as limited by `max_concurrent_downloads`.
+/// - `decode_permits`: Bounds the images being decoded and resized, as limited by `max_concurrent_decode`.
+///   Kept separate from downloads, as one is bound by the network and the other by the CPU.
+/// - `adaptive_limit`: The state of `adaptive_concurrency`, if it's enabled.
+/// - `pending_images`: The manifest positions of images whose download started but hasn't succeeded, keyed by
+///   resource name, which hold back the image cursor.
+/// - `cancel`: Set once the run should stop early, like when `MAX_RUNTIME_SECS` is exceeded.
+struct SyncContext {
+    client: ClientWithMiddleware,
+    config: SyncConfig,
+    sink: Arc<dyn OutputSink>,
+    report: Mutex<RunReport>,
+    content_hashes: Mutex<BTreeMap<String, String>>,
+    perceptual_hashes: Mutex<BTreeMap<String, String>>,
+    dimensions: Mutex<BTreeMap<String, (u32, u32)>>,
+    output_hashes: Mutex<BTreeMap<String, BTreeMap<String, String>>>,
+    last_flush: Mutex<(usize, Instant)>,
+    failures: AtomicUsize,
+    disk_full: OnceLock<String>,
+    shared_textures: Mutex<HashMap<String, SharedTexture>>,
+    download_permits: Semaphore,
+    decode_permits: Semaphore,
+    adaptive_limit: Option<Mutex<AdaptiveLimit>>,
+    pending_images: Mutex<BTreeMap<String, usize>>,
+    cancel: watch::Receiver<bool>,
+}
+
+/// How many downloads complete between adjustments of `adaptive_concurrency`.
+const ADAPTIVE_WINDOW: usize = 20;
+
+/// Struct that holds the state of the additive-increase, multiplicative-decrease controller over `download_permits`.
+/// - `limit`: How many downloads may currently be in flight.
+/// - `debt`: Permits still to be removed, as those held by downloads in flight can't be removed until released.
+/// - `succeeded`: How many downloads succeeded since the limit was last adjusted.
+/// - `failed`: How many downloads failed since the limit was last adjusted.
+/// - `backed_off`: Whether the limit was ever lowered, which ends any ramp-up.
+#[derive(Default)]
+struct AdaptiveLimit {
+    limit: usize,
+    debt: usize,
+    succeeded: usize,
+    failed: usize,
+    backed_off: bool,
+}
+
+/// A texture download shared by several manifest items, with how many of them haven't fetched it yet.
+/// The content, with the ETag it was served with, is `Err` if the texture was not found.
+type SharedTexture = (
+    usize,
+    Arc<OnceCell<Result<(Vec<u8>, Option<String>), SkipReason>>>,
+);
+
+/// Struct that holds a hash map that is updated by download tasks, and where it is saved.
+/// - `location`: The path the hash map is saved to.
+/// - `hashes`: The hashes of the processed resources, keyed by resource name.
+/// - `kinds`: The categories of the processed resources, keyed by resource name.
+/// - `etag_location`: The path the ETags are saved to.
+/// - `etags`: The ETags the content server gave for the processed resources, keyed by resource name.
+///   An ETag is replaced once a download with different content is processed, so it only ever validates the stored
+///   content. It's only sent when the stored hash is unchanged, as otherwise the stored content is known to be stale.
+/// - `previous_images`: For the image hash map, the entries loaded at the start of the phase.
+///   These keep the content hash and formats of images that aren't downloaded again.
+/// - `previous_manifest`: For the export hash map, the manifest hash at the start of the run.
+///   Periodic saves keep it, so an interrupted run doesn't skip the image phase on the next run.
+struct HashStore {
+    location: String,
+    hashes: Mutex<BTreeMap<String, String>>,
+    kinds: Mutex<BTreeMap<String, String>>,
+    etag_location: String,
+    etags: Mutex<BTreeMap<String, String>>,
+    previous_images: Option<BTreeMap<String, ImageHash>>,
+    previous_manifest: Option<String>,
+}
+
+impl HashStore {
+    /// Saves the hash map, writing to a temporary file first so a crash never leaves it partial.
+    async fn save(&self, ctx: &SyncContext) -> Result<(), Box<dyn Error>> {
+        self.write(ctx, false).await
+    }
+
+    /// Writes the hash map to its location.
+    ///
+    /// # Arguments
+    /// - `ctx`: The sync context, holding the content hashes of downloaded images.
+    /// - `partial`: Whether the phase is still running, in which case the previous manifest hash is kept.
+    async fn write(&self, ctx: &SyncContext, partial: bool) -> Result<(), Box<dyn Error>> {
+        let hashes = self.hashes.lock().await;
+        let kinds = self.kinds.lock().await;
+        let encoded = match &self.previous_images {
+            None => {
+                let mut hashes = hashes.clone();
+                if partial {
+                    match &self.previous_manifest {
+                        Some(hash) => {
+                            hashes.insert("ExportManifest.json".to_string(), hash.clone())
+                        }
+                        None => hashes.remove("ExportManifest.json"),
+                    };
+                }
+                let export_hashes: BTreeMap<String, ExportHash> = hashes
+                    .into_iter()
+                    .map(|(name, hash)| {
+                        let kind = kinds.get(&name).cloned();
+                        (name, ExportHash { hash, kind })
+                    })
+                    .collect();
+                encode_hash_map(&export_hashes, ctx.config.hash_format)?
+            }
+            Some(previous_images) => {
+                let content_hashes = ctx.content_hashes.lock().await;
+                let perceptual_hashes = ctx.perceptual_hashes.lock().await;
+                let dimensions = ctx.dimensions.lock().await;
+                let output_hashes = ctx.output_hashes.lock().await;
+                let image_formats = ctx.config.image_formats();
+                let image_hashes: BTreeMap<&String, ImageHash> = hashes
+                    .iter()
+                    .map(|(name, manifest_hash)| {
+                        let previous = previous_images
+                            .get(name)
+                            .filter(|previous| previous.manifest_hash == *manifest_hash);
+                        // Outputs that weren't written again keep their previous hashes.
+                        let mut image_output_hashes = previous
+                            .map(|previous| previous.output_hashes.clone())
+                            .unwrap_or_default();
+                        if let Some(written) = output_hashes.get(name) {
+                            image_output_hashes.extend(written.clone());
+                        }
+
+                        // Keep the previous content hash for images that weren't downloaded again.
+                        let image_hash = match content_hashes.get(name) {
+                            Some(content_hash) => ImageHash {
+                                manifest_hash: manifest_hash.clone(),
+                                content_hash: Some(content_hash.clone()),
+                                kind: kinds.get(name).cloned(),
+                                perceptual_hash: perceptual_hashes.get(name).cloned(),
+                                dimensions: dimensions.get(name).copied(),
+                                formats: image_formats.clone(),
+                                output_hashes: image_output_hashes,
+                            },
+                            None => ImageHash {
+                                manifest_hash: manifest_hash.clone(),
+                                content_hash: previous
+                                    .and_then(|previous| previous.content_hash.clone()),
+                                kind: kinds.get(name).cloned(),
+                                perceptual_hash: previous
+                                    .and_then(|previous| previous.perceptual_hash.clone()),
+                                dimensions: previous.and_then(|previous| previous.dimensions),
+                                formats: previous
+                                    .map(|previous| previous.formats.clone())
+                                    .unwrap_or_default(),
+                                output_hashes: image_output_hashes,
+                            },
+                        };
+                        (name, image_hash)
+                    })
+                    .collect();
+                encode_hash_map(&image_hashes, ctx.config.hash_format)?
+            }
+        };
+        drop(kinds);
+        drop(hashes);
+
+        write_state(&self.location, encoded).await?;
+
+        let encoded = encode_hash_map(&*self.etags.lock().await, ctx.config.hash_format)?;
+        write_state(&self.etag_location, encoded).await?;
+
+        Ok(())
+    }
+
+    /// Saves the hash map if enough downloads completed, or enough time passed, since it was last saved.
+    /// Called by download tasks after recording a hash, so an interrupted run can resume.
+    async fn flush(&self, ctx: &SyncContext) {
+        let mut last_flush = ctx.last_flush.lock().await;
+        last_flush.0 += 1;
+
+        let config = &ctx.config;
+        let due = (config.flush_every > 0 && last_flush.0 >= config.flush_every)
+            || (!config.flush_interval.is_zero()
+                && last_flush.1.elapsed() >= config.flush_interval);
+        if !due {
+            return;
+        }
+
+        if let Err(err) = self.write(ctx, true).await {
+            log!("Failed to save hashes ➞ {}: {}", self.location, err);
+        }
+        *last_flush = (0, Instant::now());
+    }
+}
+
+impl SyncContext {
+    /// Creates the state for a run, before any resource has been processed.
+    fn new(
+        client: ClientWithMiddleware,
+        config: SyncConfig,
+        sink: Arc<dyn OutputSink>,
+        cancel: watch::Receiver<bool>,
+    ) -> Self {
+        // With a ramp-up, downloads start one at a time, and `ramp_up_downloads` adds the rest.
+        let download_permits = match config.max_concurrent_downloads {
+            0 => Semaphore::MAX_PERMITS,
+            _ if config.ramps_up() => 1,
+            limit => limit,
+        };
+        let decode_permits = config.max_concurrent_decode.max(1);
+        SyncContext {
+            client,
+            sink,
+            report: Mutex::new(RunReport::default()),
+            content_hashes: Mutex::new(BTreeMap::new()),
+            perceptual_hashes: Mutex::new(BTreeMap::new()),
+            dimensions: Mutex::new(BTreeMap::new()),
+            output_hashes: Mutex::new(BTreeMap::new()),
+            last_flush: Mutex::new((0, Instant::now())),
+            failures: AtomicUsize::new(0),
+            disk_full: OnceLock::new(),
+            shared_textures: Mutex::new(HashMap::new()),
+            download_permits: Semaphore::new(download_permits),
+            decode_permits: Semaphore::new(decode_permits),
+            // Without a limit, there's nothing to back off from.
+            adaptive_limit: (config.adaptive_concurrency && config.max_concurrent_downloads > 0)
+                .then(|| {
+                    Mutex::new(AdaptiveLimit {
+                        limit: download_permits,
+                        ..AdaptiveLimit::default()
+                    })
+                }),
+            pending_images: Mutex::new(BTreeMap::new()),
+            cancel,
+            config,
+        }
+    }
+
+    /// Records whether a download succeeded, adjusting the download limit at the end of each window.
+    /// The limit is halved while too many downloads fail, and raised by one again while they succeed.
+    async fn record_download(&self, succeeded: bool) {
+        let Some(adaptive_limit) = &self.adaptive_limit else {
+            return;
+        };
+        let mut state = adaptive_limit.lock().await;
+
+        // Permits released since the limit was lowered are removed now.
+        if state.debt > 0 {
+            state.debt -= self.download_permits.forget_permits(state.debt);
+        }
+
+        if succeeded {
+            state.succeeded += 1;
+        } else {
+            state.failed += 1;
+        }
+        let total = state.succeeded + state.failed;
+        if total < ADAPTIVE_WINDOW {
+            return;
+        }
+        let failure_rate = state.failed as f64 / total as f64;
+        state.succeeded = 0;
+        state.failed = 0;
+
+        if failure_rate > self.config.adaptive_failure_rate {
+            let limit = (state.limit / 2).max(1);
+            let removed = state.limit - limit;
+            if removed == 0 {
+                return;
+            }
+            state.limit = limit;
+            state.backed_off = true;
+            state.debt += removed - self.download_permits.forget_permits(removed);
+            log!(
+                "{:.0}% of downloads failed, lowering concurrent downloads to {}",
+                failure_rate * 100.0,
+                limit
+            );
+        } else if state.limit < self.config.max_concurrent_downloads {
+            state.limit += 1;
+            // A permit that wasn't removed yet is kept instead.
+            if state.debt > 0 {
+                state.debt -= 1;
+            } else {
+                self.download_permits.add_permits(1);
+            }
+            log!("Raising concurrent downloads to {}", state.limit);
+        }
+    }
+
+    /// The position of the first manifest item whose image may not have been saved yet, to resume from.
+    ///
+    /// # Arguments
+    /// - `dispatched`: How many manifest items have been dispatched so far.
+    async fn image_cursor(&self, dispatched: usize) -> usize {
+        let pending_images = self.pending_images.lock().await;
+        pending_images
+            .values()
+            .min()
+            .map_or(dispatched, |position| dispatched.min(*position))
+    }
+
+    /// Sends an event to the configured event channel, if any.
+    fn emit(&self, event: SyncEvent) {
+        if let Some(events) = &self.config.events {
+            // The receiver may have been dropped; progress events are best-effort.
+            let _ = events.send(event);
+        }
+    }
+
+    /// Checks whether more downloads failed than `max_failures` allows, or the output volume is full.
+    ///
+    /// # Returns
+    /// - `Err(error)` once the limit is exceeded, as that many failures point to a systemic problem,
+    ///   or as soon as a download failed because the output volume is full, as every other write would too.
+    fn check_failures(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if let Some(message) = self.disk_full.get() {
+            return Err(Box::new(DiskFull {
+                message: message.clone(),
+            }));
+        }
+
+        let failures = self.failures.load(Ordering::Relaxed);
+        if self.config.max_failures > 0 && failures > self.config.max_failures {
+            return Err(format!(
+                "Aborted after {} failed downloads (MAX_FAILURES is {})",
+                failures, self.config.max_failures
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Runs a full sync: downloads changed exports, then changed images if the manifest was updated.
+/// A summary is printed at the end, and the full report is saved as `run_report.json`.
+///
+/// # Arguments
+/// - `config`: The configuration for this run.
+/// - `sink`: Where exports and images are written to, e.g. a `FileSystemSink`.
+/// - `cancel`: Stops the run early once set to `true`, saving its progress as when `MAX_RUNTIME_SECS` is exceeded.
+///
+/// # Returns
+/// - A `RunReport` once all exports and images have been processed and the hash files saved.
+pub async fn run_sync(
+    config: SyncConfig,
+    sink: Box<dyn OutputSink>,
+    cancel: watch::Receiver<bool>,
+) -> Result<RunReport, Box<dyn Error>> {
+    let started = Instant::now();
+    let started_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    start_run_log(&config, started_at)?;
+    let mut result = run_languages(config, Arc::from(sink), cancel, started, started_at).await;
+    // Any write may be the one to fill the disk, so the error is made recognizable wherever it came from.
+    if let Err(err) = &result {
+        if is_disk_full(err.as_ref()) && !err.is::<DiskFull>() {
+            result = Err(DiskFull {
+                message: err.to_string(),
+            }
+            .into());
+        }
+    }
+    if let Err(err) = &result {
+        log!("Run failed: {}", err);
+    }
+    finish_run_log();
+
+    result
+}
+
+/// Runs a sync of the configured language, or of every language in `languages` concurrently.
+///
+/// # Arguments
+/// - `config`: The configuration for this run.
+/// - `sink`: Where exports and images are written to, shared between languages.
+/// - `cancel`: Stops the run early once set to `true`.
+/// - `started`: When the run started, for measuring its duration.
+/// - `started_at`: When the run started, in seconds since the Unix epoch.
+///
+/// # Returns
+/// - A `RunReport`, combined across languages if several were synced.
+async fn run_languages(
+    mut config: SyncConfig,
+    sink: Arc<dyn OutputSink>,
+    cancel: watch::Receiver<bool>,
+    started: Instant,
+    started_at: u64,
+) -> Result<RunReport, Box<dyn Error>> {
+    if let Some(aliases_file) = &config.aliases_file {
+        let aliases: BTreeMap<String, String> = load_hash_map_from_file(aliases_file).await?;
+        config.aliases.extend(aliases);
+    }
+
+    if let Some(export_filters_file) = &config.export_filters_file {
+        let export_filters: BTreeMap<String, String> =
+            load_hash_map_from_file(export_filters_file).await?;
+        config.export_filters.extend(export_filters);
+    }
+
+    // Read up front, so a missing notice file fails the run before anything is downloaded.
+    let notice = match &config.notice_file {
+        Some(notice_file) => Some(
+            fs::read_to_string(notice_file)
+                .await
+                .map_err(|e| format!("Failed to read notice file {}: {}", notice_file, e))?,
+        ),
+        None => config.notice.clone(),
+    };
+
+    config.check_size_dirs()?;
+    check_free_space(&config).await?;
+
+    let client = match &config.client {
+        Some(client) => client.clone(),
+        None => build_client(&config)?,
+    };
+
+    if config.languages.is_empty() {
+        let output_dir = config.output_dir.clone();
+        let report = run_language(
+            client,
+            config,
+            Arc::clone(&sink),
+            cancel,
+            started,
+            started_at,
+        )
+        .await?;
+        write_notice(sink.as_ref(), &output_dir, notice.as_deref(), &report).await?;
+        return Ok(report);
+    }
+
+    // Each language gets its own hash maps and outputs, but they all share one client.
+    let mut language_set = JoinSet::new();
+    for language in &config.languages {
+        let client = client.clone();
+        let sink = Arc::clone(&sink);
+        let cancel = cancel.clone();
+        let config = config.for_language(language);
+        let language = language.clone();
+        language_set.spawn(async move {
+            let report = run_language(client, config, sink, cancel, started, started_at)
+                .await
+                .map_err(|e| (is_disk_full(e.as_ref()), e.to_string()));
+            (language, report)
+        });
+    }
+
+    let mut report = RunReport::default();
+    let mut errors = Vec::new();
+    let mut disk_full = false;
+    while let Some(result) = language_set.join_next().await {
+        match result? {
+            (language, Ok(language_report)) => report.merge(&language, language_report),
+            (language, Err((language_disk_full, err))) => {
+                disk_full |= language_disk_full;
+                errors.push(format!("{}: {}", language, err));
+            }
+        }
+    }
+    if disk_full {
+        return Err(DiskFull {
+            message: errors.join(", "),
+        }
+        .into());
+    }
+    if !errors.is_empty() {
+        return Err(format!("Failed to sync languages ({})", errors.join(", ")).into());
+    }
+
+    report.started_at = started_at;
+    report.duration_secs = started.elapsed().as_secs_f64();
+
+    log!("Run summary (all languages): {}", report);
+    fs::create_dir_all(&config.output_dir).await?;
+    let report_location = format!("{}/run_report.json", config.output_dir);
+    fs::write(&report_location, serde_json::to_string_pretty(&report)?).await?;
+    log!("Saved run report ➞ {}", report_location);
+
+    write_notice(
+        sink.as_ref(),
+        &config.output_dir,
+        notice.as_deref(),
+        &report,
+    )
+    .await?;
+
+    Ok(report)
+}
+
+/// Checks that the output volume has at least `min_free_bytes` available, if set.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the output directory and the threshold.
+///
+/// # Returns
+/// - `Err(DiskFull)` if less is available, so a run that would fill the volume part way never starts.
+async fn check_free_space(config: &SyncConfig) -> Result<(), Box<dyn Error>> {
+    let Some(min_free_bytes) = config.min_free_bytes else {
+        return Ok(());
+    };
+
+    fs::create_dir_all(&config.output_dir).await?;
+    let available = fs2::available_space(&config.output_dir)?;
+    if available < min_free_bytes {
+        return Err(DiskFull {
+            message: format!(
+                "{} bytes available on the volume of {}, but MIN_FREE_BYTES is {}",
+                available, config.output_dir, min_free_bytes
+            ),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Writes `NOTICE.txt` to the output directory, if a notice is configured and the run succeeded.
+/// It's rewritten every time, so the published mirror always carries the current notice.
+///
+/// # Arguments
+/// - `sink`: Where the notice is written to.
+/// - `output_dir`: The output directory of the run.
+/// - `notice`: The content of the notice, if any.
+/// - `report`: The report of the run.
+///
+/// # Returns
+/// - `Ok(())` once the notice has been written, or if there was none to write.
+async fn write_notice(
+    sink: &dyn OutputSink,
+    output_dir: &str,
+    notice: Option<&str>,
+    report: &RunReport,
+) -> Result<(), Box<dyn Error>> {
+    let Some(notice) = notice.filter(|_| report.succeeded()) else {
+        return Ok(());
+    };
+
+    let notice_location = format!("{}/NOTICE.txt", output_dir);
+    sink.write(&notice_location, notice.as_bytes()).await?;
+    log!("Saved notice ➞ {}", notice_location);
+
+    Ok(())
+}
+
+/// Runs a full sync of a single language, then saves its report and optional outputs.
+///
+/// # Arguments
+/// - `client`: The HTTP client to share between all requests.
+/// - `config`: The configuration for this language.
+/// - `sink`: Where exports and images are written to.
+/// - `cancel`: Stops the run early once set to `true`.
+/// - `started`: When the run started, for measuring its duration.
+/// - `started_at`: When the run started, in seconds since the Unix epoch.
+///
+/// # Returns
+/// - The `RunReport` of this language.
+async fn run_language(
+    client: ClientWithMiddleware,
+    config: SyncConfig,
+    sink: Arc<dyn OutputSink>,
+    cancel: watch::Receiver<bool>,
+    started: Instant,
+    started_at: u64,
+) -> Result<RunReport, Box<dyn Error>> {
+    // Outputs outside of the output directory are copied by the directory they belong to.
+    let delta_dir = config.delta_dir(started_at);
+    let sink: Arc<dyn OutputSink> = if config.emit_delta_dir {
+        Arc::new(DeltaSink {
+            inner: sink,
+            roots: vec![
+                (format!("{}/", config.output_dir), delta_dir.clone()),
+                (
+                    format!("{}/", config.export_dir()),
+                    format!("{}/export", delta_dir),
+                ),
+                (
+                    format!("{}/", config.image_dir()),
+                    format!("{}/image", delta_dir),
+                ),
+            ],
+        })
+    } else {
+        sink
+    };
+
+    let ctx = Arc::new(SyncContext::new(client, config, sink, cancel));
+
+    let ramp_up = ctx
+        .config
+        .ramps_up()
+        .then(|| tokio::spawn(ramp_up_downloads(Arc::clone(&ctx))));
+    let outcome = sync(&ctx).await;
+    if let Some(ramp_up) = ramp_up {
+        ramp_up.abort();
+    }
+    let outcome = outcome?;
+
+    let mut report = ctx.report.lock().await.clone();
+    report.outcome = outcome;
+    report.started_at = started_at;
+    report.duration_secs = started.elapsed().as_secs_f64();
+
+    log!("Run summary: {}", report);
+    let report_location = format!("{}/run_report.json", ctx.config.output_dir);
+    let report_json = serde_json::to_string_pretty(&report)?;
+    fs::write(&report_location, &report_json).await?;
+    log!("Saved run report ➞ {}", report_location);
+
+    // The delta only exists if something was written, and its report describes what changed.
+    if ctx.config.emit_delta_dir && Path::new(&delta_dir).is_dir() {
+        fs::write(format!("{}/run_report.json", delta_dir), &report_json).await?;
+        log!("Saved delta ➞ {}", delta_dir);
+    }
+
+    if ctx.config.emit_inventory {
+        write_inventory(&ctx.config, &report).await?;
+    }
+
+    if ctx.config.emit_changelog {
+        write_changelog(&ctx.config, &report).await?;
+    }
+
+    // Rebuilt whenever exports may have changed, or if it was never written.
+    let combined_location = format!("{}/combined.json", ctx.config.output_dir);
+    if !ctx.config.combined_exports.is_empty()
+        && (outcome == SyncOutcome::Updated || !Path::new(&combined_location).is_file())
+    {
+        write_combined(&ctx.config, ctx.sink.as_ref(), &combined_location).await?;
+    }
+
+    if ctx.config.emit_metrics {
+        write_metrics(&ctx.config, &report).await?;
+    }
+
+    Ok(report)
+}
+
+/// Raises the download permits one at a time, evenly spread over `ramp_up`, until `max_concurrent_downloads` is reached.
+/// Runs in the background while the run starts, and stops early if `adaptive_concurrency` backs off.
+///
+/// # Arguments
+/// - `ctx`: The state shared between all tasks of the run, holding the download permits.
+async fn ramp_up_downloads(ctx: Arc<SyncContext>) {
+    let max = ctx.config.max_concurrent_downloads;
+    let step = ctx.config.ramp_up / (max - 1) as u32;
+    for permits in 2..=max {
+        sleep(step).await;
+        if let Some(adaptive_limit) = &ctx.adaptive_limit {
+            let mut state = adaptive_limit.lock().await;
+            if state.backed_off || state.limit >= max {
+                return;
+            }
+            state.limit += 1;
+        }
+        ctx.download_permits.add_permits(1);
+        if permits == max {
+            log!("Ramped up to {} concurrent downloads", max);
+        }
+    }
+}
+
+/// Writes `combined.json`, merging the entries of the configured exports into one object keyed by unique name.
+/// Entries are read from the cached sources and annotated with the export they came from, under `sourceExport`.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the exports to combine and the rendering options.
+/// - `sink`: Where the combined dataset is written to.
+/// - `location`: The path of `combined.json`.
+///
+/// # Returns
+/// - `Ok(())` once the combined dataset has been written.
+async fn write_combined(
+    config: &SyncConfig,
+    sink: &dyn OutputSink,
+    location: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut sources = Vec::new();
+    let mut entries = fs::read_dir(config.source_dir()).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let kind = resource_kind(&file_name).to_lowercase();
+        if config.combined_exports.contains(&kind) {
+            sources.push(file_name);
+        }
+    }
+    sources.sort();
+
+    let mut combined = serde_json::Map::new();
+    for file_name in sources {
+        let name = file_name.strip_suffix(".json").unwrap_or(&file_name);
+        let content = fs::read_to_string(format!("{}/{}", config.source_dir(), file_name)).await?;
+        let parsed: serde_json::Value = serde_json::from_str(&content)?;
+
+        // Exports hold one or more arrays of entries, e.g. `ExportWeapons` and `ExportRailjackWeapons`.
+        let Some(object) = parsed.as_object() else {
+            log!(
+                "Skipped {} for combined.json, export is not an object",
+                name
+            );
+            continue;
+        };
+        for item in object
+            .values()
+            .filter_map(|value| value.as_array())
+            .flatten()
+        {
+            let Some(unique_name) = item.get("uniqueName").and_then(|value| value.as_str()) else {
+                continue;
+            };
+            let mut entry = item.clone();
+            if let Some(entry) = entry.as_object_mut() {
+                entry.insert("sourceExport".to_string(), name.into());
+            }
+            if combined.contains_key(unique_name) {
+                log!(
+                    "Duplicate {} in {} for combined.json, keeping the first",
+                    unique_name,
+                    name
+                );
+                continue;
+            }
+            combined.insert(
+                unique_name.to_string(),
+                transform_keys(entry, config.key_case),
+            );
+        }
+    }
+
+    sink.write(location, &serde_json::to_vec(&combined)?)
+        .await?;
+    log!(
+        "Saved combined dataset ➞ {} ({} entries)",
+        location,
+        combined.len()
+    );
+
+    Ok(())
+}
+
+/// Appends the added and updated resources of a run to `CHANGELOG.md`, if there were any.
+/// Resources are named after the entry with the same unique name in the cached exports, if there is one.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the output directories.
+/// - `report`: The report of the run that just finished.
+///
+/// # Returns
+/// - `Ok(())` once the changelog was appended to, or if nothing changed.
+async fn write_changelog(config: &SyncConfig, report: &RunReport) -> Result<(), Box<dyn Error>> {
+    if report.added.is_empty() && report.updated.is_empty() {
+        return Ok(());
+    }
+
+    let mut names = BTreeMap::new();
+    let mut entries = fs::read_dir(config.source_dir()).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let content = fs::read_to_string(entry.path()).await?;
+        let Ok(serde_json::Value::Object(object)) = serde_json::from_str(&content) else {
+            continue;
+        };
+        for item in object
+            .values()
+            .filter_map(|value| value.as_array())
+            .flatten()
+        {
+            let unique_name = item.get("uniqueName").and_then(|value| value.as_str());
+            let name = item.get("name").and_then(|value| value.as_str());
+            if let (Some(unique_name), Some(name)) = (unique_name, name) {
+                names
+                    .entry(unique_name.to_string())
+                    .or_insert_with(|| name.to_string());
+            }
+        }
+    }
+
+    let changelog_location = format!("{}/CHANGELOG.md", config.output_dir);
+    let mut changelog = match fs::read_to_string(&changelog_location).await {
+        Ok(changelog) => changelog + "\n",
+        Err(err) if err.kind() == ErrorKind::NotFound => "# Changelog\n\n".to_string(),
+        Err(err) => return Err(err.into()),
+    };
+    changelog.push_str(&report.to_changelog(&names));
+    fs::write(&changelog_location, changelog).await?;
+    log!("Saved changelog ➞ {}", changelog_location);
+
+    Ok(())
+}
+
+/// Writes `inventory.csv`, listing every resource in both hash maps.
+/// `last_updated_run` is carried over from the previous inventory, except for resources changed this run.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the output directories.
+/// - `report`: The report of the run that just finished.
+///
+/// # Returns
+/// - `Ok(())` if the inventory was written successfully.
+async fn write_inventory(config: &SyncConfig, report: &RunReport) -> Result<(), Box<dyn Error>> {
+    let inventory_location = format!("{}/inventory.csv", config.output_dir);
+
+    // The last four columns never contain commas, so the name is whatever remains on the left.
+    let mut last_updated: HashMap<String, String> = HashMap::new();
+    if let Ok(previous) = fs::read_to_string(&inventory_location).await {
+        for line in previous.lines().skip(1) {
+            let mut fields = line.rsplitn(5, ',');
+            if let (Some(run), Some(name)) = (fields.next(), fields.nth(3)) {
+                let name = name.trim_matches('"').replace("\"\"", "\"");
+                last_updated.insert(name, run.to_string());
+            }
+        }
+    }
+    for name in report.added.iter().chain(&report.updated) {
+        last_updated.insert(name.clone(), report.started_at.to_string());
+    }
+
+    let export_hashes: BTreeMap<String, ExportHash> =
+        load_hash_map_from_file(&config.hash_location("export")).await?;
+    let image_hashes: BTreeMap<String, ImageHash> =
+        load_hash_map_from_file(&config.hash_location("image")).await?;
+    let image_format = config.format_for(512);
+
+    let mut csv = String::from("name,hash,type,size_bytes,last_updated_run\n");
+    let rows = export_hashes
+        .iter()
+        .map(|(name, export)| {
+            let path = format!(
+                "{}/{}{}",
+                config.export_dir(),
+                name,
+                config.store_compressed.suffix()
+            );
+            (name, &export.hash, "export", path)
+        })
+        .chain(image_hashes.iter().map(|(name, hash)| {
+            let path = image_path(
+                config,
+                &config.image_dir(),
+                &image_output_name(name),
+                512,
+                hash.format_for(512).unwrap_or(image_format),
+            );
+            (name, &hash.manifest_hash, "image", path)
+        }));
+    for (name, hash, kind, path) in rows {
+        let size_bytes = match fs::metadata(&path).await {
+            Ok(metadata) => metadata.len().to_string(),
+            Err(..) => String::new(),
+        };
+        let field = if name.contains([',', '"']) {
+            format!("\"{}\"", name.replace('"', "\"\""))
+        } else {
+            name.clone()
+        };
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            field,
+            hash,
+            kind,
+            size_bytes,
+            last_updated
+                .get(name.as_str())
+                .map_or("", |run| run.as_str())
+        ));
+    }
+
+    fs::write(&inventory_location, csv).await?;
+    log!("Saved inventory ➞ {}", inventory_location);
+
+    Ok(())
+}
+
+/// Writes `metrics.prom` for a node_exporter textfile collector.
+/// The last success timestamp is carried over from the previous file when this run did not succeed.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the output directory.
+/// - `report`: The report of the run that just finished.
+///
+/// # Returns
+/// - `Ok(())` if the metrics were written successfully.
+async fn write_metrics(config: &SyncConfig, report: &RunReport) -> Result<(), Box<dyn Error>> {
+    let metrics_location = format!("{}/metrics.prom", config.output_dir);
+
+    let last_success = if report.succeeded() {
+        Some(report.started_at + report.duration_secs as u64)
+    } else {
+        fs::read_to_string(&metrics_location)
+            .await
+            .ok()
+            .and_then(|previous| {
+                previous.lines().find_map(|line| {
+                    line.strip_prefix("warframe_export_last_success_timestamp ")
+                        .and_then(|value| value.trim().parse().ok())
+                })
+            })
+    };
+
+    // Written through a temporary file, so the collector never reads a partial file.
+    write_state(&metrics_location, report.to_prometheus(last_success)).await?;
+    log!("Saved metrics ➞ {}", metrics_location);
+
+    Ok(())
+}
+
+/// Runs the export and image phases of a sync.
+///
+/// # Arguments
+/// - `ctx`: The state shared between all tasks of the run.
+///
+/// # Returns
+/// - The `SyncOutcome` of the run.
+async fn sync(ctx: &Arc<SyncContext>) -> Result<SyncOutcome, Box<dyn Error>> {
+    let client = &ctx.client;
+    let config = &ctx.config;
+    let deadline = Deadline {
+        at: config
+            .max_runtime
+            .map(|max_runtime| Instant::now() + max_runtime),
+        cancel: ctx.cancel.clone(),
+    };
+
+    let output_dir = &config.output_dir;
+
+    if config.force {
+        log!("Force mode is active, every resource will be downloaded again!");
+    }
+
+    let storage_folders = [
+        format!("{}/", output_dir),
+        config.image_dir(),
+        config.export_dir(),
+        config.source_dir(),
+    ];
+
+    let export_hash_location = config.hash_location("export");
+    let image_hash_location = config.hash_location("image");
+    let version_location = format!("{}/version.json", output_dir);
+
+    // Create missing data folders.
+    for folder in &storage_folders {
+        if !Path::new(folder).is_dir() {
+            log!("{} directory not found, initializing...", folder);
+            fs::create_dir_all(folder).await?;
+        }
+    }
+
+    let mut state_locations = vec![
+        version_location.clone(),
+        config.etag_location("export"),
+        config.etag_location("image"),
+        format!("{}/metrics.prom", output_dir),
+    ];
+    for format in [HashFormat::Json, HashFormat::MsgPack] {
+        state_locations.push(config.hash_location_as("export", format));
+        state_locations.push(config.hash_location_as("image", format));
+    }
+    remove_temp_files(&state_locations).await?;
+
+    // Create missing resize-directory data folders.
+    for size in IMAGE_SIZES {
+        let folder = format!("{}/{}", &storage_folders[1], config.size_dir(*size));
+        if !Path::new(&folder).is_dir() {
+            log!("{} directory not found, initializing...", folder);
+            fs::create_dir_all(folder).await?;
+        }
+    }
+
+    let mut updated_hash = false;
+    let mut updated_manifest = false;
+
+    recover_corrupt_state(config, &version_location).await?;
+    let mut version = load_version_from_file(&version_location).await?;
+
+    convert_hash_map::<ExportHash>(config, "export").await?;
+    convert_hash_map::<ImageHash>(config, "image").await?;
+    rebuild_hash_maps(client, config).await?;
+
+    let mut stored_exports: BTreeMap<String, ExportHash> =
+        load_hash_map_from_file(&export_hash_location).await?;
+    reformat_images(config, ctx.sink.as_ref(), &image_hash_location).await?;
+    prune_orphan_sizes(config).await?;
+    if verify_content(config, &image_hash_location).await? {
+        // Process the manifest again, so the damaged images are downloaded.
+        stored_exports.remove("ExportManifest.json");
+        version.index_etag = None;
+        version.last_version = None;
+    }
+
+    if rerender_exports(config, ctx.sink.as_ref(), &mut stored_exports, &mut version).await? {
+        let encoded = encode_hash_map(&stored_exports, config.hash_format)?;
+        write_state(&export_hash_location, encoded).await?;
+        write_state(&version_location, serde_json::to_string(&version)?).await?;
+    }
+    let export_kinds = stored_exports
+        .iter()
+        .filter_map(|(name, export)| Some((name.clone(), export.kind.clone()?)))
+        .collect();
+    let export_hashes: BTreeMap<String, String> = stored_exports
+        .into_iter()
+        .map(|(name, export)| (name, export.hash))
+        .collect();
+
+    let mut export_set: JoinSet<bool> = JoinSet::new();
+    let export_store = Arc::new(HashStore {
+        location: export_hash_location.clone(),
+        previous_manifest: export_hashes.get("ExportManifest.json").cloned(),
+        hashes: Mutex::new(export_hashes),
+        kinds: Mutex::new(export_kinds),
+        etag_location: config.etag_location("export"),
+        etags: Mutex::new(load_etag_map_from_file(&config.etag_location("export")).await?),
+        previous_images: None,
+    });
+
+    // With a manifest file, the index and exports are skipped and only images are processed.
+    let export_index;
+    let mut export_resources = Vec::new();
+    let mut index_etag = None;
+    let mut upstream_version = None;
+    let mut index_hash = None;
+    let mut index_order = None;
+    if let Some(manifest_file) = &config.manifest_file {
+        log!("Using the manifest at {}, skipping exports", manifest_file);
+        updated_manifest = true;
+    } else if !config.runs_phase(SyncPhase::Exports) {
+        log!("The export phase is disabled, processing images from the stored manifest");
+        updated_manifest = true;
+    } else {
+        // A cheap version check skips the run before the index is even downloaded.
+        if let Some(version_check_url) = &config.version_check_url {
+            let current_version = fetch_upstream_version(client, config, version_check_url).await?;
+            if !config.force && version.last_version.as_deref() == Some(current_version.as_str()) {
+                log!(
+                    "Upstream version is unchanged ({}), no exports to update!",
+                    current_version
+                );
+                return Ok(SyncOutcome::Unchanged);
+            }
+            upstream_version = Some(current_version);
+        }
+
+        // In force mode, the index is fetched and processed even if it is unchanged.
+        let previous_etag = if config.force {
+            None
+        } else {
+            version.index_etag.as_deref()
+        };
+        let (content, etag) = match download_export_index(config, previous_etag).await? {
+            ExportIndex::Unchanged => {
+                log!("Export index is unchanged, no exports to update!");
+                return Ok(SyncOutcome::Unchanged);
+            }
+            ExportIndex::Updated { content, etag } => (content, etag),
+        };
+        export_index = content;
+
+        // A cheap check of whether anything could have changed, without diffing the index.
+        if config.track_index_hash {
+            let hash = blake3::hash(export_index.as_bytes()).to_hex().to_string();
+            match &version.index_hash {
+                Some(previous) if *previous == hash => {
+                    log!("Export index content is unchanged ({})", hash)
+                }
+                Some(previous) => log!("Export index content changed ({} ➞ {})", previous, hash),
+                None => log!("Export index content hash is {}", hash),
+            }
+            index_hash = Some(hash);
+        }
+
+        for line in index_entries(&export_index) {
+            let resource = split_string_to_resource(line)?;
+            // Entries are only processed by an allowed extension, rather than assuming `.json`.
+            let extension = Path::new(&resource.name)
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .map(|extension| extension.to_lowercase());
+            match extension {
+                Some(extension) if config.export_extensions.contains(&extension) => {
+                    export_resources.push((line, resource))
+                }
+                _ => log!(
+                    "Skipped {}, its extension is not in EXPORT_EXTENSIONS",
+                    resource.name
+                ),
+            }
+        }
+
+        if config.track_index_order {
+            let order: Vec<String> = index_entries(&export_index)
+                .map(|line| split_string_to_resource(line).map(|resource| resource.name))
+                .collect::<Result<_, _>>()?;
+            if let Some(previous) = &version.index_order {
+                if index_order_changed(previous, &order) {
+                    log!("Export index order changed since the last run");
+                    ctx.report.lock().await.index_reordered = true;
+                }
+            }
+            index_order = Some(order);
+        }
+
+        // A truncated or error response can still decompress, so refuse to sync against a tiny index.
+        let tracked = export_store.hashes.lock().await.len();
+        if export_resources.len() < config.min_index_entries && tracked > export_resources.len() {
+            return Err(format!(
+                "Export index only has {} entries (minimum is {}, {} are tracked), refusing to sync",
+                export_resources.len(),
+                config.min_index_entries,
+                tracked
+            )
+            .into());
+        }
+
+        let export_diff = diff_hash_maps(
+            &*export_store.hashes.lock().await,
+            &export_resources
+                .iter()
+                .map(|(_, resource)| (resource.name.clone(), resource.hash.clone()))
+                .collect(),
+        );
+        log!("Exports: {}", export_diff);
+
+        if config.schedule == Schedule::LargestFirst {
+            let mut sizes = HashMap::new();
+            for (line, resource) in &export_resources {
+                if export_diff.added.contains(&resource.name)
+                    || export_diff.updated.contains(&resource.name)
+                {
+                    let url = manifest_url(config.export_url(), line)?.to_string();
+                    sizes.insert(
+                        resource.name.clone(),
+                        content_length(client, config, &url).await,
+                    );
+                }
+            }
+            export_resources.sort_by_key(|(_, resource)| {
+                Reverse(sizes.get(&resource.name).copied().flatten().unwrap_or(0))
+            });
+        }
+
+        index_etag = etag;
+    }
+
+    ctx.emit(SyncEvent::PhaseChanged(SyncPhase::Exports));
+    let mut timed_out = false;
+    for (line, resource) in export_resources {
+        if deadline.is_past() {
+            timed_out = true;
+            break;
+        }
+        if let Err(err) = ctx.check_failures() {
+            // Saving may fail too when the disk is full, which shouldn't hide why the run stopped.
+            if let Err(save_err) = export_store.save(ctx).await {
+                log!(
+                    "Failed to save hashes ➞ {}: {}",
+                    export_hash_location,
+                    save_err
+                );
+            }
+            return Err(err);
+        }
+
+        // JSON exports are rendered under several extensions, so their name drops it.
+        let name = resource
+            .name
+            .strip_suffix(".json")
+            .unwrap_or(&resource.name)
+            .to_string();
+        let url = manifest_url(config.export_url(), line)?.to_string();
+        let (hash, manifest) = check_and_download_resource(
+            ctx,
+            &export_store,
+            &mut export_set,
+            Arc::new(resource),
+            Arc::new(DownloadConfig {
+                url,
+                path: storage_folders[2].clone(),
+                name,
+                as_text: true,
+            }),
+        )
+        .await?;
+
+        // Any hash got updated, only set once.
+        if hash {
+            updated_hash = true;
+            // Specifically, Manifest hash was updated.
+            if manifest {
+                updated_manifest = true;
+            }
+        }
+    }
+
+    // Wait for all downloads to finish...
+    let (mut all_succeeded, drain_timed_out) = wait_for_downloads(export_set, &deadline).await;
+    timed_out |= drain_timed_out;
+
+    // A run that stopped at a batch boundary left a cursor, so its images are resumed.
+    let pending_images = updated_manifest || version.image_cursor.is_some();
+    let mut stopped_early = false;
+    if updated_hash {
+        export_store.save(ctx).await?;
+        log!("Saved export hashes ➞ {}", export_hash_location);
+    }
+    ctx.check_failures().map_err(|e| e as Box<dyn Error>)?;
+
+    if updated_hash || pending_images {
+        if timed_out {
+            log!("Maximum runtime exceeded, skipping image downloads!");
+        } else if pending_images && !config.runs_phase(SyncPhase::Images) {
+            log!("The image phase is disabled, skipping image downloads!");
+        } else if pending_images {
+            ctx.emit(SyncEvent::PhaseChanged(SyncPhase::Images));
+            let mut image_set = JoinSet::new();
+            let previous_images: BTreeMap<String, ImageHash> =
+                load_hash_map_from_file(&image_hash_location).await?;
+            let image_store = Arc::new(HashStore {
+                location: image_hash_location.clone(),
+                hashes: Mutex::new(
+                    previous_images
+                        .iter()
+                        .map(|(name, hash)| (name.clone(), hash.manifest_hash.clone()))
+                        .collect(),
+                ),
+                kinds: Mutex::new(
+                    previous_images
+                        .iter()
+                        .filter_map(|(name, hash)| Some((name.clone(), hash.kind.clone()?)))
+                        .collect(),
+                ),
+                etag_location: config.etag_location("image"),
+                etags: Mutex::new(load_etag_map_from_file(&config.etag_location("image")).await?),
+                previous_images: Some(previous_images),
+                previous_manifest: None,
+            });
+
+            // Prefer the cached source, as the written export may have had its keys renamed.
+            let mut manifest_location = format!("{}/ExportManifest.json", config.source_dir());
+            if let Some(manifest_file) = &config.manifest_file {
+                manifest_location = manifest_file.clone();
+            } else if !Path::new(&manifest_location).is_file() {
+                manifest_location = format!("{}/{}", &storage_folders[2], "ExportManifest.json");
+            }
+            let export_manifest = match read_export_manifest(&manifest_location)
+                .await
+                .map_err(|e| e.to_string())
+            {
+                Ok(export_manifest) => export_manifest,
+                // A provided manifest won't be fixed by downloading it again, nor one this run doesn't download.
+                Err(err)
+                    if config.manifest_file.is_some() || !config.runs_phase(SyncPhase::Exports) =>
+                {
+                    return Err(err.into())
+                }
+                Err(err) => {
+                    // Forget the manifest hash, so the next run downloads it again.
+                    export_store
+                        .hashes
+                        .lock()
+                        .await
+                        .remove("ExportManifest.json");
+                    export_store.save(ctx).await?;
+                    return Err(format!(
+                        "{}; it will be downloaded again on the next run, please re-run",
+                        err
+                    )
+                    .into());
+                }
+            };
+
+            let image_resources = export_manifest
+                .Manifest
+                .into_iter()
+                .map(|item| {
+                    let resource = split_string_to_resource(&item.texture_location)?;
+                    Ok((item, resource.hash))
+                })
+                .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+            if config.emit_names_list && updated_manifest {
+                let mut names: Vec<&str> = image_resources
+                    .iter()
+                    .map(|(item, _)| item.unique_name.as_str())
+                    .collect();
+                names.sort_unstable();
+                names.dedup();
+                let names_location = format!("{}/names.txt", config.output_dir);
+                let names_list = names.join("\n") + "\n";
+                ctx.sink
+                    .write(&names_location, names_list.as_bytes())
+                    .await?;
+                log!("Saved names list ➞ {}", names_location);
+            }
+
+            // Items sharing a texture download it once, and each writes its own outputs from it.
+            let mut texture_uses: HashMap<String, usize> = HashMap::new();
+            for (item, _) in &image_resources {
+                let url = texture_url(config.image_url(), &item.texture_location)?;
+                *texture_uses.entry(url.to_string()).or_default() += 1;
+            }
+            texture_uses.retain(|_, uses| *uses > 1);
+            if !texture_uses.is_empty() {
+                log!(
+                    "Images: {} items share {} textures",
+                    texture_uses.values().sum::<usize>(),
+                    texture_uses.len()
+                );
+            }
+            *ctx.shared_textures.lock().await = texture_uses
+                .into_iter()
+                .map(|(url, uses)| (url, (uses, Arc::new(OnceCell::new()))))
+                .collect();
+
+            let image_diff = diff_hash_maps(
+                &*image_store.hashes.lock().await,
+                &image_resources
+                    .iter()
+                    .map(|(item, hash)| (item.unique_name.clone(), hash.clone()))
+                    .collect(),
+            );
+            log!("Images: {}", image_diff);
+
+            // A changed manifest is processed from the start, as earlier items may have changed too.
+            let resume_from = match version.image_cursor {
+                Some(cursor) if !updated_manifest => cursor,
+                _ => 0,
+            };
+            if resume_from > 0 {
+                log!("Resuming images from item {}", resume_from);
+            }
+
+            let image_count = image_resources.len();
+            let mut batches = 0;
+            let mut dispatched = resume_from;
+            let mut last_checkpoint = (0, Instant::now());
+            for (
+                position,
+                (
+                    ExportManifestItem {
+                        texture_location,
+                        unique_name,
+                    },
+                    hash,
+                ),
+            ) in image_resources.into_iter().enumerate().skip(resume_from)
+            {
+                if deadline.is_past() {
+                    timed_out = true;
+                    break;
+                }
+                if let Err(err) = ctx.check_failures() {
+                    if let Err(save_err) = image_store.save(ctx).await {
+                        log!(
+                            "Failed to save hashes ➞ {}: {}",
+                            image_hash_location,
+                            save_err
+                        );
+                    }
+                    version.image_cursor = Some(ctx.image_cursor(dispatched).await);
+                    write_state(&version_location, serde_json::to_string(&version)?).await?;
+                    return Err(err);
+                }
+
+                let url = texture_url(config.image_url(), &texture_location)?.to_string();
+                // Added before the download starts, so it can't succeed before it's tracked.
+                ctx.pending_images
+                    .lock()
+                    .await
+                    .insert(unique_name.clone(), position);
+                let (spawned, _) = check_and_download_resource(
+                    ctx,
+                    &image_store,
+                    &mut image_set,
+                    Arc::new(Resource {
+                        name: unique_name.clone(),
+                        hash,
+                    }),
+                    Arc::new(DownloadConfig {
+                        url,
+                        path: storage_folders[1].clone(),
+                        // The extension is added per output format when saving.
+                        name: image_output_name(&unique_name),
+                        as_text: false,
+                    }),
+                )
+                .await?;
+                if spawned {
+                    last_checkpoint.0 += 1;
+                } else {
+                    ctx.pending_images.lock().await.remove(&unique_name);
+                }
+                dispatched = position + 1;
+
+                // Between batches, checkpoint as often as the hash files are flushed, so an interrupted run
+                // resumes near where it stopped instead of checking every item again.
+                let checkpoint_due = (config.flush_every > 0
+                    && last_checkpoint.0 >= config.flush_every)
+                    || (!config.flush_interval.is_zero()
+                        && last_checkpoint.1.elapsed() >= config.flush_interval);
+                if checkpoint_due {
+                    image_store.save(ctx).await?;
+                    version.image_cursor = Some(ctx.image_cursor(dispatched).await);
+                    write_state(&version_location, serde_json::to_string(&version)?).await?;
+                    last_checkpoint = (0, Instant::now());
+                }
+
+                // Checkpoint at batch boundaries, so a stopped run resumes from here.
+                let processed = position + 1;
+                if config.batch_size > 0
+                    && (processed - resume_from) % config.batch_size == 0
+                    && processed < image_count
+                {
+                    let batch_set = std::mem::take(&mut image_set);
+                    let (batch_succeeded, drain_timed_out) =
+                        wait_for_downloads(batch_set, &deadline).await;
+                    all_succeeded &= batch_succeeded;
+                    timed_out |= drain_timed_out;
+
+                    image_store.save(ctx).await?;
+                    version.image_cursor = Some(processed);
+                    write_state(&version_location, serde_json::to_string(&version)?).await?;
+                    log!(
+                        "Checkpoint: {} of {} images processed",
+                        processed,
+                        image_count
+                    );
+
+                    batches += 1;
+                    if config.max_batches_per_run > 0 && batches >= config.max_batches_per_run {
+                        log!(
+                            "Stopped after {} batches, the next run resumes at item {}",
+                            batches,
+                            processed
+                        );
+                        stopped_early = true;
+                        break;
+                    }
+                }
+            }
+
+            // Wait for all downloads to finish...
+            let (images_succeeded, drain_timed_out) =
+                wait_for_downloads(image_set, &deadline).await;
+            all_succeeded &= images_succeeded;
+            timed_out |= drain_timed_out;
+
+            image_store.save(ctx).await?;
+            log!("Saved image hashes ➞ {}", &image_hash_location);
+            ctx.check_failures().map_err(|e| e as Box<dyn Error>)?;
+            // Textures of unchanged items are never fetched, so drop what's left.
+            ctx.shared_textures.lock().await.clear();
+
+            if timed_out && !stopped_early {
+                // Downloads aborted after the grace period are still pending, so they're resumed too.
+                let cursor = ctx.image_cursor(dispatched).await;
+                version.image_cursor = Some(cursor);
+                write_state(&version_location, serde_json::to_string(&version)?).await?;
+                log!("Stopped at item {} of {} images", cursor, image_count);
+            } else if !stopped_early && version.image_cursor.take().is_some() {
+                write_state(&version_location, serde_json::to_string(&version)?).await?;
+            }
+        } else {
+            log!("No changes found in export manifest!")
+        }
+    } else {
+        log!("No exports to update!");
+    }
+
+    if timed_out {
+        if *ctx.cancel.borrow() {
+            log!("Run cancelled, progress has been saved!");
+        } else {
+            log!("Maximum runtime exceeded, progress has been saved!");
+        }
+        return Ok(SyncOutcome::TimedOut);
+    }
+
+    // Only remember the index and upstream version once everything was processed, so failures are retried.
+    if all_succeeded && !stopped_early {
+        let mut changed = false;
+        if index_etag.is_some() && index_etag != version.index_etag {
+            version.index_etag = index_etag;
+            changed = true;
+        }
+        if upstream_version.is_some() && upstream_version != version.last_version {
+            version.last_version = upstream_version;
+            changed = true;
+        }
+        if index_hash.is_some() && index_hash != version.index_hash {
+            version.index_hash = index_hash;
+            changed = true;
+        }
+        if index_order.is_some() && index_order != version.index_order {
+            version.index_order = index_order;
+            changed = true;
+        }
+        if changed {
+            let json = serde_json::to_string(&version)?;
+            log!("Saved version ➞ {}", version_location);
+            write_state(&version_location, json).await?;
+        }
+    }
+
+    // Images resumed from a cursor can change even when no export did.
+    let resumed_changes = {
+        let report = ctx.report.lock().await;
+        !report.added.is_empty() || !report.updated.is_empty()
+    };
+    if updated_hash || resumed_changes {
+        Ok(SyncOutcome::Updated)
+    } else {
+        Ok(SyncOutcome::Unchanged)
+    }
+}
+
+/// Reads and parses the export manifest written during the export phase.
+/// The file is checked to be complete first, so a partial write from an earlier crash gets a clear error.
+///
+/// # Arguments
+/// - `path`: The path of `ExportManifest.json`.
+///
+/// # Returns
+/// - The parsed `ExportManifest`.
+async fn read_export_manifest(path: &str) -> Result<ExportManifest, Box<dyn Error>> {
+    let content = fs::read_to_string(path)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+    if !content.trim_end().ends_with('}') {
+        return Err(format!(
+            "{} appears to be truncated ({} bytes), likely from an interrupted run",
+            path,
+            content.len()
+        )
+        .into());
+    }
+
+    parse_export_manifest(&content)
+}
+
+/// Parses a sanitized export and writes all of its rendered outputs.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the rendering options.
+/// - `sink`: Where the outputs are written to.
+/// - `path`: The directory to write the outputs to.
+/// - `name`: The export name, without extension.
+/// - `sanitized`: The export content, with control characters already escaped.
+/// - `emit_patch`: Whether to write a JSON Patch against the previously written version.
+///
+/// # Returns
+/// - `Ok(())` if all outputs were written successfully.
+async fn write_export(
+    config: &SyncConfig,
+    sink: &dyn OutputSink,
+    path: &str,
+    name: &str,
+    sanitized: &str,
+    emit_patch: bool,
+) -> Result<(), Box<dyn Error>> {
+    let mut parsed_json = match config.export_filter(name) {
+        Some(filter) => apply_filter(sanitized, filter)?,
+        None => serde_json::from_str(sanitized)?,
+    };
+    if config.export_unwrap {
+        parsed_json = unwrap_export(parsed_json).unwrap_or_else(|value| {
+            log!("Export {} is not a single-key object, written as is", name);
+            value
+        });
+    }
+    let parsed_json = transform_keys(parsed_json, config.key_case);
+
+    // Read the previous version before it gets overwritten, to diff against.
+    let compression = config.store_compressed;
+    let min_path = format!("{}/{}.min.json", path, name);
+    let stored_min_path = format!("{}{}", min_path, compression.suffix());
+    let previous_json: Option<serde_json::Value> =
+        if emit_patch && config.emit_json_patch && Path::new(&stored_min_path).is_file() {
+            let previous = compression.decompress(&fs::read(&stored_min_path).await?)?;
+            serde_json::from_slice(&previous).ok()
+        } else {
+            None
+        };
+
+    let mut written = vec![
+        (min_path, serde_json::to_vec(&parsed_json)?),
+        (
+            format!("{}/{}.json", path, name),
+            serde_json::to_vec_pretty(&parsed_json)?,
+        ),
+    ];
+
+    if let Some(previous_json) = previous_json {
+        written.push((
+            format!("{}/{}.patch.json", path, name),
+            serde_json::to_vec(&json_patch::diff(&previous_json, &parsed_json))?,
+        ));
+    }
+
+    if config.emit_ndjson {
+        match to_ndjson(&parsed_json) {
+            Some(ndjson) => written.push((format!("{}/{}.ndjson", path, name), ndjson.into())),
+            None => log!("Skipped NDJSON for {}, export is not an array", name),
+        }
+    }
+
+    // Compressed outputs are stored under their own suffix, e.g. `.min.json.gz`.
+    if compression != Compression::None {
+        written = written
+            .into_iter()
+            .map(|(path, bytes)| {
+                let compressed = compression.compress(&bytes)?;
+                Ok((format!("{}{}", path, compression.suffix()), compressed))
+            })
+            .collect::<Result<_, io::Error>>()?;
+    }
+
+    for (path, bytes) in &written {
+        sink.write(path, bytes).await?;
+    }
+
+    copy_to_alias(sink, config, name, &written).await
+}
+
+/// Writes a state file, such as a hash map or `version.json`, to a temporary file first and then renames it into
+/// place, so a crash mid-write never leaves it truncated.
+///
+/// # Arguments
+/// - `location`: The path of the state file.
+/// - `contents`: The contents to write.
+///
+/// # Returns
+/// - `Ok(())` once the state file has been replaced.
+async fn write_state(location: &str, contents: impl AsRef<[u8]>) -> Result<(), Box<dyn Error>> {
+    let temporary_location = format!("{}.tmp", location);
+    fs::write(&temporary_location, contents).await?;
+    fs::rename(&temporary_location, location).await?;
+    Ok(())
+}
+
+/// Caches the sanitized source of an export in the source directory, so its outputs can be re-rendered without a
+/// network fetch. Sources are state read back by later runs, so they're written to disk rather than through the
+/// `OutputSink`.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the source directory.
+/// - `name`: The name of the export, e.g. `ExportWeapons_en`.
+/// - `sanitized`: The sanitized content of the export.
+///
+/// # Returns
+/// - `Ok(())` once the source has been written.
+async fn write_source(
+    config: &SyncConfig,
+    name: &str,
+    sanitized: &str,
+) -> Result<(), Box<dyn Error>> {
+    fs::write(format!("{}/{}.json", config.source_dir(), name), sanitized).await?;
+    Ok(())
+}
+
+/// Removes temporary files left behind by a run that crashed mid-write.
+/// Only the exact temporary files this tool writes, `{location}.tmp`, are removed, so files of other tools in
+/// the same folders are left alone.
+///
+/// # Arguments
+/// - `locations`: The files that are written through a temporary file, e.g. the hash maps.
+///
+/// # Returns
+/// - `Ok(())` once every leftover temporary file has been removed.
+async fn remove_temp_files(locations: &[String]) -> Result<(), Box<dyn Error>> {
+    for location in locations {
+        let temporary_location = format!("{}.tmp", location);
+        match fs::remove_file(&temporary_location).await {
+            Ok(()) => log!("Removed leftover temporary file ➞ {}", temporary_location),
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves aside state files that fail to load, when `recover_corrupt_state` is enabled.
+/// The run then starts from empty state, verifying every resource again, rather than failing until the file is fixed.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the output directory and hash format.
+/// - `version_location`: The path of `version.json`.
+///
+/// # Returns
+/// - `Ok(())` once every state file that remains can be loaded, or immediately if recovery is disabled.
+async fn recover_corrupt_state(
+    config: &SyncConfig,
+    version_location: &str,
+) -> Result<(), Box<dyn Error>> {
+    if !config.recover_corrupt_state {
+        return Ok(());
+    }
+
+    let mut corrupt = Vec::new();
+    for format in [HashFormat::Json, HashFormat::MsgPack] {
+        let location = config.hash_location_as("export", format);
+        if let Err(err) = load_hash_map_from_file::<ExportHash>(&location).await {
+            corrupt.push((location, err.to_string()));
+        }
+        let location = config.hash_location_as("image", format);
+        if let Err(err) = load_hash_map_from_file::<ImageHash>(&location).await {
+            corrupt.push((location, err.to_string()));
+        }
+    }
+    for name in ["export", "image"] {
+        let location = config.etag_location(name);
+        if let Err(err) = load_etag_map_from_file(&location).await {
+            corrupt.push((location, err.to_string()));
+        }
+    }
+    if let Err(err) = load_version_from_file(version_location).await {
+        corrupt.push((version_location.to_string(), err.to_string()));
+    }
+
+    if corrupt.is_empty() {
+        return Ok(());
+    }
+
+    let mut images_lost = false;
+    for (location, err) in corrupt {
+        let backup_location = format!("{}.corrupt", location);
+        fs::rename(&location, &backup_location).await?;
+        log!(
+            "[RECOVERED] {} could not be loaded ({}), moved to {} and starting from empty state",
+            location,
+            err,
+            backup_location
+        );
+        images_lost |= location.contains("/image_hash.");
+    }
+
+    // Forget what would let the run exit early, so the recovered state is verified again.
+    let mut version = load_version_from_file(version_location).await?;
+    let index_etag = version.index_etag.take();
+    let last_version = version.last_version.take();
+    if index_etag.is_some() || last_version.is_some() {
+        write_state(version_location, serde_json::to_string(&version)?).await?;
+    }
+    if images_lost {
+        for format in [HashFormat::Json, HashFormat::MsgPack] {
+            let location = config.hash_location_as("export", format);
+            let mut export_hashes: BTreeMap<String, ExportHash> =
+                load_hash_map_from_file(&location).await?;
+            if export_hashes.remove("ExportManifest.json").is_some() {
+                let encoded = encode_hash_map(&export_hashes, format)?;
+                write_state(&location, encoded).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Converts a hash map stored in another `HASH_FORMAT` to the configured one, so changing it doesn't download everything again.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the output directory and hash format.
+/// - `name`: The name of the hash map, e.g. `export` or `image`.
+///
+/// # Returns
+/// - `Ok(())` once the hash map is stored in the configured format, if it was stored at all.
+async fn convert_hash_map<T: Serialize + DeserializeOwned>(
+    config: &SyncConfig,
+    name: &str,
+) -> Result<(), Box<dyn Error>> {
+    let location = config.hash_location(name);
+    if Path::new(&location).is_file() {
+        return Ok(());
+    }
+
+    for format in [HashFormat::Json, HashFormat::MsgPack] {
+        let previous_location = config.hash_location_as(name, format);
+        if format == config.hash_format || !Path::new(&previous_location).is_file() {
+            continue;
+        }
+
+        let hashes: BTreeMap<String, T> = load_hash_map_from_file(&previous_location).await?;
+        let encoded = encode_hash_map(&hashes, config.hash_format)?;
+        write_state(&location, encoded).await?;
+        fs::remove_file(&previous_location).await?;
+        log!("Converted {} ➞ {}", previous_location, location);
+        break;
+    }
+
+    Ok(())
+}
+
+/// Re-encodes stored images whose output formats no longer match the configuration.
+/// Images are regenerated from their stored original, and files in formats that are no longer written are deleted.
+/// Images whose original is missing are dropped from the hash map, so they're downloaded again.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the image options.
+/// - `sink`: Where the regenerated images are written to.
+/// - `image_hash_location`: The path of the image hash map.
+///
+/// # Returns
+/// - `Ok(())` once every stored image is in the configured formats.
+async fn reformat_images(
+    config: &SyncConfig,
+    sink: &dyn OutputSink,
+    image_hash_location: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut image_hashes: BTreeMap<String, ImageHash> =
+        load_hash_map_from_file(image_hash_location).await?;
+    let image_formats = config.image_formats();
+    let image_dir = config.image_dir();
+
+    let mut changed = false;
+    let mut missing = Vec::new();
+    for (unique_name, image_hash) in image_hashes.iter_mut() {
+        if image_hash.formats == image_formats {
+            continue;
+        }
+        changed = true;
+
+        // Only sizes that were written before need new formats; formats no longer configured are removed.
+        let mut added: Vec<(u32, OutputFormat)> = Vec::new();
+        let mut removed: Vec<(u32, OutputFormat)> = Vec::new();
+        for (size, formats) in &image_formats {
+            let previous = image_hash.formats_for(*size);
+            if previous.is_empty() {
+                continue;
+            }
+            added.extend(
+                formats
+                    .iter()
+                    .filter(|format| !previous.contains(format))
+                    .map(|format| (*size, *format)),
+            );
+            removed.extend(
+                previous
+                    .iter()
+                    .filter(|format| !formats.contains(format))
+                    .map(|format| (*size, *format)),
+            );
+        }
+
+        let name = image_output_name(unique_name);
+        if !added.is_empty() {
+            let original_format = image_hash.format_for(512).unwrap_or(config.format_for(512));
+            let original_path = image_path(config, &image_dir, &name, 512, original_format);
+            let Ok(decoded) = ImageReader::open(&original_path)
+                .and_then(|reader| reader.with_guessed_format())
+                .map_err(Box::<dyn Error>::from)
+                .and_then(|reader| Ok(reader.decode()?))
+            else {
+                missing.push(unique_name.clone());
+                continue;
+            };
+            let raw_image = to_resize_source(config, decoded)?;
+
+            let mut written = Vec::new();
+            for (size, format) in added {
+                let path = image_path(config, &image_dir, &name, size, format);
+                let resized_buf = resize_image(
+                    &raw_image,
+                    size,
+                    format,
+                    config.filter_for(size),
+                    config.sharpen_for(size),
+                )
+                .await?;
+                sink.write(&path, &resized_buf).await?;
+                image_hash.output_hashes.insert(
+                    ImageHash::output_key(size, format),
+                    blake3::hash(&resized_buf).to_hex().to_string(),
+                );
+                written.push((path, resized_buf));
+            }
+            copy_to_alias(sink, config, &name, &written).await?;
+            log!("[REFORMATTED] ➞ {}", name);
+        }
+        for (size, format) in removed {
+            fs::remove_file(image_path(config, &image_dir, &name, size, format))
+                .await
+                .ok();
+            image_hash
+                .output_hashes
+                .remove(&ImageHash::output_key(size, format));
+        }
+
+        image_hash.formats = image_formats.clone();
+    }
+
+    if !missing.is_empty() {
+        log!(
+            "{} images have no stored original and will be downloaded again",
+            missing.len()
+        );
+        for unique_name in missing {
+            image_hashes.remove(&unique_name);
+        }
+    }
+
+    if changed {
+        let encoded = encode_hash_map(&image_hashes, config.hash_format)?;
+        write_state(image_hash_location, encoded).await?;
+    }
+
+    Ok(())
+}
+
+/// Removes the size directories of the image directory, e.g. `48x48`, that are no longer written to, when
+/// `prune_orphan_sizes` is enabled: their size is no longer in `IMAGE_SIZES`, or `size_dirs` renamed it.
+/// Directories named by `size_dirs` are pruned the same way once their size is no longer in `IMAGE_SIZES`.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the image directory.
+///
+/// # Returns
+/// - `Ok(())` once every orphaned size directory has been removed.
+async fn prune_orphan_sizes(config: &SyncConfig) -> Result<(), Box<dyn Error>> {
+    if !config.prune_orphan_sizes {
+        return Ok(());
+    }
+
+    let image_dir = config.image_dir();
+    let mut entries = match fs::read_dir(&image_dir).await {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+        if !entry.file_type().await?.is_dir() {
+            continue;
+        }
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let in_use = IMAGE_SIZES
+            .iter()
+            .any(|size| config.size_dir(*size) == file_name);
+        let default_named = file_name
+            .split_once('x')
+            .is_some_and(|(width, height)| width == height && width.parse::<u32>().is_ok());
+        // A directory `size_dirs` names for a size that's no longer resized to is orphaned too.
+        let configured = config.size_dirs.values().any(|name| *name == file_name);
+        if in_use || !(default_named || configured) {
+            continue;
+        }
+
+        fs::remove_dir_all(entry.path()).await?;
+        log!("[PRUNED] ➞ {}/{}", image_dir, file_name);
+    }
+
+    Ok(())
+}
+
+/// The output file name of an image, without extension, e.g. `Lotus.Interface.Icons.Foo`.
+fn image_output_name(unique_name: &str) -> String {
+    unique_name.replace("/", ".")[1..].to_string()
+}
+
+/// The path of an image output of the given size (`512` is the original).
+/// Resized outputs are written to the directory of their size, as named by `size_dir`.
+fn image_path(
+    config: &SyncConfig,
+    image_dir: &str,
+    name: &str,
+    size: u32,
+    format: OutputFormat,
+) -> String {
+    if size == 512 {
+        format!("{}/{}.{}", image_dir, name, format.extension())
+    } else {
+        format!(
+            "{}/{}/{}.{}",
+            image_dir,
+            config.size_dir(size),
+            name,
+            format.extension()
+        )
+    }
+}
+
+/// Rebuilds missing hash maps from the outputs that exist, when `rebuild_hash_from_files` is enabled.
+/// The export index and manifest are downloaded to learn the current hashes, and every resource with
+/// an existing output is assumed to be up to date, so it isn't downloaded again. The manifest itself is
+/// left out, so the image phase still runs and downloads the images that are missing.
+///
+/// # Arguments
+/// - `client`: The HTTP client to download the export index and manifest with.
+/// - `config`: The sync configuration, providing the output directories.
+///
+/// # Returns
+/// - `Ok(())` once the hash maps have been rebuilt, or if none was missing.
+async fn rebuild_hash_maps(
+    client: &ClientWithMiddleware,
+    config: &SyncConfig,
+) -> Result<(), Box<dyn Error>> {
+    let export_hash_location = config.hash_location("export");
+    let image_hash_location = config.hash_location("image");
+    if !config.rebuild_hash_from_files
+        || (Path::new(&export_hash_location).is_file() && Path::new(&image_hash_location).is_file())
+    {
+        return Ok(());
+    }
+
+    log!("Hash maps are missing, rebuilding them from existing files...");
+    let index = match download_export_index(config, None).await? {
+        ExportIndex::Updated { content, .. } => content,
+        ExportIndex::Unchanged => return Err("Export index was not returned".into()),
+    };
+
+    let mut exports: BTreeMap<String, ExportHash> =
+        load_hash_map_from_file(&export_hash_location).await?;
+    let export_count = exports.len();
+    for line in index_entries(&index) {
+        let resource = split_string_to_resource(line)?;
+        if resource.name == "ExportManifest.json" || exports.contains_key(&resource.name) {
+            continue;
+        }
+        let path = format!(
+            "{}/{}{}",
+            config.export_dir(),
+            resource.name,
+            config.store_compressed.suffix()
+        );
+        if Path::new(&path).is_file() {
+            let kind = Some(resource_kind(&resource.name));
+            exports.insert(
+                resource.name,
+                ExportHash {
+                    hash: resource.hash,
+                    kind,
+                },
+            );
+        }
+    }
+
+    let manifest = download_listed_manifest(client, config, &index).await?;
+    let mut images: BTreeMap<String, ImageHash> =
+        load_hash_map_from_file(&image_hash_location).await?;
+    let image_count = images.len();
+    let image_dir = config.image_dir();
+    for item in manifest.Manifest {
+        if images.contains_key(&item.unique_name) {
+            continue;
+        }
+        let name = image_output_name(&item.unique_name);
+        // Only the formats that were found are recorded, so missing outputs get regenerated.
+        let formats: BTreeMap<u32, Vec<OutputFormat>> = config
+            .image_formats()
+            .into_iter()
+            .map(|(size, formats)| {
+                let existing = formats
+                    .into_iter()
+                    .filter(|format| {
+                        Path::new(&image_path(config, &image_dir, &name, size, *format)).is_file()
+                    })
+                    .collect::<Vec<_>>();
+                (size, existing)
+            })
+            .filter(|(_, formats)| !formats.is_empty())
+            .collect();
+        if !formats.contains_key(&512) {
+            continue;
+        }
+        let manifest_hash = split_string_to_resource(&item.texture_location)?.hash;
+        let kind = Some(resource_kind(&item.unique_name));
+        images.insert(
+            item.unique_name,
+            ImageHash {
+                manifest_hash,
+                content_hash: None,
+                kind,
+                perceptual_hash: None,
+                dimensions: None,
+                formats,
+                output_hashes: BTreeMap::new(),
+            },
+        );
+    }
+
+    log!(
+        "Rebuilt hash maps: {} exports and {} images found on disk",
+        exports.len() - export_count,
+        images.len() - image_count
+    );
+    let encoded = encode_hash_map(&exports, config.hash_format)?;
+    write_state(&export_hash_location, encoded).await?;
+    let encoded = encode_hash_map(&images, config.hash_format)?;
+    write_state(&image_hash_location, encoded).await?;
+
+    Ok(())
+}
+
+/// Checks stored images against the hashes of their outputs as written, when `verify_content_on_start` is enabled.
+/// Any output that doesn't match its hash is damaged. Entries written before output hashes were recorded fall back
+/// to the content hash for originals kept as downloaded, and otherwise must still decode.
+/// Damaged images are dropped from the hash map, so they're downloaded again.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the image directory.
+/// - `image_hash_location`: The path of the image hash map.
+///
+/// # Returns
+/// - `true` if any image was damaged and dropped from the hash map.
+async fn verify_content(
+    config: &SyncConfig,
+    image_hash_location: &str,
+) -> Result<bool, Box<dyn Error>> {
+    if !config.verify_content_on_start {
+        return Ok(false);
+    }
+
+    let mut image_hashes: BTreeMap<String, ImageHash> =
+        load_hash_map_from_file(image_hash_location).await?;
+    let image_dir = config.image_dir();
+
+    let mut damaged = Vec::new();
+    for (unique_name, image_hash) in &image_hashes {
+        if image_hash.content_hash.is_none() && image_hash.output_hashes.is_empty() {
+            continue;
+        }
+        let name = image_output_name(unique_name);
+        'outputs: for (size, formats) in &image_hash.formats {
+            for format in formats {
+                // Missing outputs aren't checked, as skipped images never had any.
+                let path = image_path(config, &image_dir, &name, *size, *format);
+                let Ok(content) = fs::read(&path).await else {
+                    continue;
+                };
+                let hash = blake3::hash(&content).to_hex().to_string();
+                let damage = match image_hash
+                    .output_hashes
+                    .get(&ImageHash::output_key(*size, *format))
+                {
+                    Some(expected) if *expected != hash => {
+                        Some("does not match its hash as written".to_string())
+                    }
+                    Some(..) => None,
+                    None if *size == 512 && image_hash.content_hash.as_ref() == Some(&hash) => None,
+                    None => verify_image(&path, *size)
+                        .await
+                        .err()
+                        .map(|e| e.to_string()),
+                };
+                if let Some(damage) = damage {
+                    log!("[DAMAGED] ➞ {}: {}", name, damage);
+                    damaged.push(unique_name.clone());
+                    break 'outputs;
+                }
+            }
+        }
+    }
+
+    if damaged.is_empty() {
+        log!("Verified the stored images, none are damaged");
+        return Ok(false);
+    }
+
+    log!(
+        "{} images are damaged and will be downloaded again",
+        damaged.len()
+    );
+    for unique_name in damaged {
+        image_hashes.remove(&unique_name);
+    }
+    let encoded = encode_hash_map(&image_hashes, config.hash_format)?;
+    write_state(image_hash_location, encoded).await?;
+
+    Ok(true)
+}
+
+/// Splits an image into frames, if it's animated or a sprite strip.
+/// Animated PNG, GIF and WebP images are split into their frames, composited onto the full canvas.
+/// Other images are split into a grid of square frames of `frame_size`, if their dimensions are a multiple of it.
+///
+/// # Arguments
+/// - `content`: The encoded image, to read animation frames from.
+/// - `decoded`: The decoded image, to slice sprite strips from.
+/// - `frame_size`: The width and height of the frames of sprite strips, if they're split.
+///
+/// # Returns
+/// - The frames in order, or none if the image has fewer than two.
+fn animation_frames(
+    content: &[u8],
+    decoded: &DynamicImage,
+    frame_size: Option<u32>,
+) -> Result<Vec<DynamicImage>, Box<dyn Error>> {
+    let frames = match image::guess_format(content) {
+        Ok(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(Cursor::new(content))?;
+            if decoder.is_apng()? {
+                decoder.apng()?.into_frames().collect_frames()?
+            } else {
+                Vec::new()
+            }
+        }
+        Ok(ImageFormat::Gif) => GifDecoder::new(Cursor::new(content))?
+            .into_frames()
+            .collect_frames()?,
+        Ok(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(Cursor::new(content))?;
+            if decoder.has_animation() {
+                decoder.into_frames().collect_frames()?
+            } else {
+                Vec::new()
+            }
+        }
+        _ => Vec::new(),
+    };
+    if frames.len() > 1 {
+        return Ok(frames
+            .into_iter()
+            .map(|frame| DynamicImage::ImageRgba8(frame.into_buffer()))
+            .collect());
+    }
+
+    let Some(frame_size) = frame_size.filter(|frame_size| *frame_size > 0) else {
+        return Ok(Vec::new());
+    };
+    let (width, height) = decoded.dimensions();
+    if width % frame_size != 0
+        || height % frame_size != 0
+        || (width == frame_size && height == frame_size)
+    {
+        return Ok(Vec::new());
+    }
+    let mut frames = Vec::new();
+    for y in (0..height).step_by(frame_size as usize) {
+        for x in (0..width).step_by(frame_size as usize) {
+            frames.push(decoded.crop_imm(x, y, frame_size, frame_size));
+        }
+    }
+    Ok(frames)
+}
+
+/// Whether a decoded image is a blank placeholder: at most 1 pixel wide or high, or fully transparent.
+fn is_placeholder(image: &DynamicImage) -> bool {
+    if image.width() <= 1 || image.height() <= 1 {
+        return true;
+    }
+    image.color().has_alpha() && image.to_rgba8().pixels().all(|pixel| pixel[3] == 0)
+}
+
+/// Reads back a written image and decodes it, to catch truncated, corrupt, or wrongly sized outputs.
+///
+/// # Arguments
+/// - `path`: The path of the written image.
+/// - `size`: The expected width and height of the image.
+///
+/// # Returns
+/// - `Ok(())` if the file decodes successfully and is `size`x`size`.
+async fn verify_image(path: &str, size: u32) -> Result<(), Box<dyn Error>> {
+    let content = fs::read(path).await?;
+    let decoded = ImageReader::new(Cursor::new(&content))
+        .with_guessed_format()?
+        .decode()
+        .map_err(|e| format!("Output {} failed verification: {}", path, e))?;
+
+    if decoded.width() != size || decoded.height() != size {
+        return Err(format!(
+            "Output {} failed verification: expected {}x{}, but it is {}x{}",
+            path,
+            size,
+            size,
+            decoded.width(),
+            decoded.height()
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Converts a decoded image into the source for resizing, flattening it if configured.
+/// With `optimize_grayscale`, images whose color channels are all equal drop the redundant ones.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the optional background color.
+/// - `decoded`: The decoded image.
+///
+/// # Returns
+/// - A `U8x3` image if flattened, otherwise a `U8x4` image, or `U8` and `U8x2` respectively for grayscale images.
+fn to_resize_source(
+    config: &SyncConfig,
+    decoded: DynamicImage,
+) -> Result<Image<'static>, Box<dyn Error>> {
+    let rgba_image = decoded.to_rgba8();
+    let (width, height) = rgba_image.dimensions();
+    let is_grayscale = |pixels: &[u8], channels: usize| {
+        config.optimize_grayscale
+            && pixels
+                .chunks_exact(channels)
+                .all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2])
+    };
+
+    Ok(match config.flatten_background {
+        Some(background) => {
+            let flattened = flatten_background(&rgba_image, background).into_raw();
+            if is_grayscale(&flattened, 3) {
+                let luma = flattened.chunks_exact(3).map(|pixel| pixel[0]).collect();
+                Image::from_vec_u8(width, height, luma, PixelType::U8)?
+            } else {
+                Image::from_vec_u8(width, height, flattened, PixelType::U8x3)?
+            }
+        }
+        None if is_grayscale(rgba_image.as_raw(), 4) => {
+            let luma_alpha = rgba_image
+                .chunks_exact(4)
+                .flat_map(|pixel| [pixel[0], pixel[3]])
+                .collect();
+            Image::from_vec_u8(width, height, luma_alpha, PixelType::U8x2)?
+        }
+        None => Image::from_vec_u8(width, height, rgba_image.into_raw(), PixelType::U8x4)?,
+    })
+}
+
+/// Copies the outputs of a resource to its legacy name, if it has an alias.
+///
+/// # Arguments
+/// - `sink`: Where the copies are written to.
+/// - `config`: The sync configuration, providing the alias map.
+/// - `name`: The output name of the resource, without extension.
+/// - `written`: The paths and contents of the outputs that were written, each starting with `name` in their file name.
+///
+/// # Returns
+/// - `Ok(())` if there is no alias, or all outputs were copied successfully.
+async fn copy_to_alias(
+    sink: &dyn OutputSink,
+    config: &SyncConfig,
+    name: &str,
+    written: &[(String, Vec<u8>)],
+) -> Result<(), Box<dyn Error>> {
+    let Some(alias) = config.aliases.get(name) else {
+        return Ok(());
+    };
+
+    for (path, bytes) in written {
+        let path = Path::new(path);
+        let suffix = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(|file_name| file_name.strip_prefix(name))
+            .ok_or_else(|| format!("Output {} does not match {}", path.display(), name))?;
+        let alias_path = path.with_file_name(format!("{}{}", alias, suffix));
+        sink.write(&alias_path.to_string_lossy(), bytes).await?;
+    }
+    log!("[ALIASED] {} ➞ {}", name, alias);
+
+    Ok(())
+}
+
+/// Re-renders every tracked export from its cached source when the rendering options changed.
+/// Exports without a cached source are dropped from the hash map, so they're downloaded again.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the rendering options.
+/// - `sink`: Where the regenerated exports are written to.
+/// - `export_hashes`: The stored export hashes, keyed by file name (e.g. `ExportWeapons_en.json`).
+/// - `version`: The run state, holding the fingerprint of the last rendering options.
+///
+/// # Returns
+/// - `true` if the rendering options changed since the last run.
+async fn rerender_exports(
+    config: &SyncConfig,
+    sink: &dyn OutputSink,
+    export_hashes: &mut BTreeMap<String, ExportHash>,
+    version: &mut VersionInfo,
+) -> Result<bool, Box<dyn Error>> {
+    let fingerprint = config.export_fingerprint();
+    if version.export_fingerprint.as_deref() == Some(fingerprint.as_str()) {
+        return Ok(false);
+    }
+
+    // Without a previous fingerprint, assume the outputs are already up to date.
+    if version.export_fingerprint.is_some() {
+        log!("Export rendering options changed, regenerating exports...");
+
+        let export_dir = config.export_dir();
+        let mut missing = Vec::new();
+        for key in export_hashes.keys() {
+            // Only JSON exports are rendered.
+            let Some(name) = key.strip_suffix(".json") else {
+                continue;
+            };
+            let source_path = format!("{}/{}.json", config.source_dir(), name);
+            if !Path::new(&source_path).is_file() {
+                missing.push(key.clone());
+                continue;
+            }
+
+            let sanitized = fs::read_to_string(&source_path).await?;
+            write_export(config, sink, &export_dir, name, &sanitized, false).await?;
+            log!("[REGENERATED] ➞ {}", name);
+        }
+
+        if !missing.is_empty() {
+            log!(
+                "{} exports have no cached source and will be downloaded again",
+                missing.len()
+            );
+            for key in missing {
+                export_hashes.remove(&key);
+            }
+            version.index_etag = None;
+            version.last_version = None;
+        }
+    }
+
+    version.export_fingerprint = Some(fingerprint);
+    Ok(true)
+}
+
+/// Requests only the headers of a resource to find its size.
+///
+/// # Arguments
+/// - `client`: HTTP client for making the request.
+/// - `config`: The sync configuration, providing the extra query parameters.
+/// - `url`: The URL of the resource.
+///
+/// # Returns
+/// - `Some(length)` if the server reported a `Content-Length`, or `None` otherwise.
+async fn content_length(
+    client: &ClientWithMiddleware,
+    config: &SyncConfig,
+    url: &str,
+) -> Option<u64> {
+    let response = client
+        .head(config.request_url(url).ok()?)
+        .send()
+        .await
+        .ok()?;
+    response
+        .headers()
+        .get(CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Struct that holds when a run stops dispatching downloads.
+/// - `at`: When `MAX_RUNTIME_SECS` is exceeded, if it's set.
+/// - `cancel`: Set once the run is cancelled, which stops it as if `at` had passed.
+struct Deadline {
+    at: Option<Instant>,
+    cancel: watch::Receiver<bool>,
+}
+
+impl Deadline {
+    /// Checks whether the maximum runtime was exceeded or the run was cancelled.
+    fn is_past(&self) -> bool {
+        *self.cancel.borrow() || self.at.is_some_and(|at| Instant::now() >= at)
+    }
+
+    /// Waits until the maximum runtime is exceeded or the run is cancelled, whichever comes first.
+    async fn reached(&self) {
+        let mut cancel = self.cancel.clone();
+        let cancelled = async move {
+            // Once the sender is gone, the run can no longer be cancelled.
+            if cancel.wait_for(|cancelled| *cancelled).await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        };
+        match self.at {
+            Some(at) => tokio::select! {
+                _ = sleep_until(at) => {},
+                _ = cancelled => {},
+            },
+            None => cancelled.await,
+        }
+    }
+}
+
+/// Waits for all spawned downloads to finish.
+/// Once the deadline has passed, in-flight downloads are given `SHUTDOWN_GRACE` before being aborted.
+///
+/// # Arguments
+/// - `join_set`: The set of download tasks, each yielding whether it succeeded.
+/// - `deadline`: When the run stops dispatching downloads.
+///
+/// # Returns
+/// - A tuple `(all_succeeded, timed_out)`.
+async fn wait_for_downloads(join_set: JoinSet<bool>, deadline: &Deadline) -> (bool, bool) {
+    let mut results = pin!(join_set.join_all());
+    tokio::select! {
+        results = &mut results => return (results.into_iter().all(|ok| ok), deadline.is_past()),
+        _ = deadline.reached() => {},
+    }
+
+    // Dropping the join set on timeout aborts whatever is still running.
+    match timeout(SHUTDOWN_GRACE, results).await {
+        Ok(results) => (results.into_iter().all(|ok| ok), true),
+        Err(..) => (false, true),
+    }
+}
+
+/// Builds the HTTP client shared between all requests, retrying transient failures.
+/// With the `http-cache` feature and `http_cache_dir` set, responses are also cached on disk.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the cache settings.
+///
+/// # Returns
+/// - A `ClientWithMiddleware` ready for use.
+pub(crate) fn build_client(config: &SyncConfig) -> Result<ClientWithMiddleware, Box<dyn Error>> {
+    build_client_retrying(config, true)
+}
+
+/// Builds the HTTP client for the export index, like `build_client`, except that connect failures aren't retried
+/// by the client, as `download_export_index` retries them itself with the longer `connect_retries` backoff.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the cache settings.
+///
+/// # Returns
+/// - A `ClientWithMiddleware` ready for use.
+fn build_index_client(config: &SyncConfig) -> Result<ClientWithMiddleware, Box<dyn Error>> {
+    build_client_retrying(config, false)
+}
+
+/// Builds an HTTP client retrying transient failures, and connect failures only if `retry_connect` is set.
+fn build_client_retrying(
+    config: &SyncConfig,
+    retry_connect: bool,
+) -> Result<ClientWithMiddleware, Box<dyn Error>> {
+    #[allow(unused_mut)]
+    let mut builder = ClientBuilder::new(
+        Client::builder()
+            .redirect(Policy::limited(MAX_REDIRECTS))
+            .build()?,
+    );
+
+    #[cfg(feature = "http-cache")]
+    if let Some(cache_dir) = &config.http_cache_dir {
+        use http_cache_reqwest::{CACacheManager, Cache, HttpCache, HttpCacheOptions};
+
+        builder = builder.with(Cache(HttpCache {
+            mode: parse_cache_mode(&config.http_cache_mode)?,
+            manager: CACacheManager {
+                path: cache_dir.into(),
+            },
+            options: HttpCacheOptions::default(),
+        }));
+    }
+
+    let retry_policy = ExponentialBackoff::builder().build_with_max_retries(3);
+    let retry_strategy = StatusRetryStrategy {
+        statuses: config.retry_statuses.clone(),
+        tls_error: config.tls_error,
+        retry_connect,
+    };
+    Ok(builder
+        .with(RetryTransientMiddleware::new_with_policy_and_strategy(
+            retry_policy,
+            retry_strategy,
+        ))
+        .build())
+}
+
+/// Struct that decides which responses are retried, by their status code.
+/// Network errors are retried as with the default strategy, and so are successful responses that declare an empty body.
+/// - `statuses`: The status codes to retry, e.g. `503`; any other unsuccessful response is final.
+/// - `tls_error`: Whether TLS and certificate errors are retried like other network errors.
+/// - `retry_connect`: Whether DNS lookup and connection failures are retried, or left to the caller.
+struct StatusRetryStrategy {
+    statuses: Vec<u16>,
+    tls_error: TlsErrorMode,
+    retry_connect: bool,
+}
+
+impl RetryableStrategy for StatusRetryStrategy {
+    fn handle(
+        &self,
+        res: &Result<reqwest::Response, reqwest_middleware::Error>,
+    ) -> Option<Retryable> {
+        match res {
+            // Some proxies answer with an empty body when they fail, which a retry usually fixes.
+            Ok(response)
+                if response.status().is_success()
+                    && response
+                        .headers()
+                        .get(CONTENT_LENGTH)
+                        .is_some_and(|length| length == "0") =>
+            {
+                Some(Retryable::Transient)
+            }
+            Ok(response) if response.status().is_success() => None,
+            Ok(response) if self.statuses.contains(&response.status().as_u16()) => {
+                Some(Retryable::Transient)
+            }
+            Ok(_) => Some(Retryable::Fatal),
+            Err(error)
+                if self.tls_error == TlsErrorMode::Abort && tls_error_message(error).is_some() =>
+            {
+                Some(Retryable::Fatal)
+            }
+            Err(error) if !self.retry_connect && connect_failure(error).is_some() => {
+                Some(Retryable::Fatal)
+            }
+            Err(error) => default_on_request_failure(error),
+        }
+    }
+}
+
+/// Finds the TLS or certificate error behind a failed request, if that's why it failed.
+///
+/// # Arguments
+/// - `error`: The error of the request, possibly wrapped by the retry middleware.
+///
+/// # Returns
+/// - `Some(message)` with the innermost TLS error message, or `None` for any other error.
+fn tls_error_message(error: &reqwest_middleware::Error) -> Option<String> {
+    let error = match error {
+        reqwest_middleware::Error::Reqwest(error) => error,
+        reqwest_middleware::Error::Middleware(error) => {
+            return match error.downcast_ref::<RetryError>()? {
+                RetryError::WithRetries { err, .. } | RetryError::Error(err) => {
+                    tls_error_message(err)
+                }
+            };
+        }
+    };
+    if !error.is_connect() {
+        return None;
+    }
+
+    // The TLS backend's message is the most specific, e.g. `certificate verify failed`.
+    let mut message = None;
+    let mut source = error.source();
+    while let Some(error) = source {
+        let text = error.to_string();
+        let lowercase = text.to_lowercase();
+        if ["tls", "ssl", "certificate", "handshake"]
+            .iter()
+            .any(|keyword| lowercase.contains(keyword))
+        {
+            message = Some(text);
+        }
+        source = error.source();
+    }
+    message
+}
+
+/// Describes why a request failed to connect, if it failed before reaching the server.
+/// TLS errors are left out, as they're handled by `TlsErrorMode`.
+///
+/// # Arguments
+/// - `error`: The error of the request, possibly wrapped by the retry middleware.
+///
+/// # Returns
+/// - `Some((failure, message))`, where `failure` is `DNS lookup` if the host couldn't be resolved and `Connection`
+///   for any other connect error, and `message` is the innermost error; or `None` if the request reached the server.
+fn connect_failure(error: &reqwest_middleware::Error) -> Option<(&'static str, String)> {
+    if tls_error_message(error).is_some() {
+        return None;
+    }
+    let error = match error {
+        reqwest_middleware::Error::Reqwest(error) => error,
+        reqwest_middleware::Error::Middleware(error) => {
+            return match error.downcast_ref::<RetryError>()? {
+                RetryError::WithRetries { err, .. } | RetryError::Error(err) => {
+                    connect_failure(err)
+                }
+            };
+        }
+    };
+    if !error.is_connect() {
+        return None;
+    }
+
+    let mut failure = "Connection";
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(error) = source {
+        message = error.to_string();
+        let lowercase = message.to_lowercase();
+        if lowercase.contains("dns") || lowercase.contains("lookup") {
+            failure = "DNS lookup";
+        }
+        source = error.source();
+    }
+    Some((failure, message))
+}
+
+/// Converts a failed request into an error, surfacing the TLS error message if there is one.
+///
+/// # Arguments
+/// - `error`: The error of the request.
+///
+/// # Returns
+/// - The error to report, which otherwise only names the URL for TLS errors.
+fn request_error(error: reqwest_middleware::Error) -> Box<dyn Error> {
+    match tls_error_message(&error) {
+        Some(message) => format!("TLS error: {} ({})", message, error).into(),
+        None => error.into(),
+    }
+}
+
+/// Parses an HTTP cache mode name, such as `default` or `force-cache`.
+///
+/// # Arguments
+/// - `mode`: The kebab-case name of the cache mode.
+///
+/// # Returns
+/// - The matching `CacheMode`, or an error if the name is unknown.
+#[cfg(feature = "http-cache")]
+fn parse_cache_mode(mode: &str) -> Result<http_cache_reqwest::CacheMode, Box<dyn Error>> {
+    use http_cache_reqwest::CacheMode;
+
+    match mode.to_lowercase().as_str() {
+        "default" => Ok(CacheMode::Default),
+        "no-store" => Ok(CacheMode::NoStore),
+        "reload" => Ok(CacheMode::Reload),
+        "no-cache" => Ok(CacheMode::NoCache),
+        "force-cache" => Ok(CacheMode::ForceCache),
+        "only-if-cached" => Ok(CacheMode::OnlyIfCached),
+        "ignore-rules" => Ok(CacheMode::IgnoreRules),
+        other => Err(format!("Unknown HTTP cache mode: {}", other).into()),
+    }
+}
+
+/// Fetches the upstream content version from `version_check_url`.
+///
+/// # Arguments
+/// - `client`: A reference to the HTTP client used for making requests.
+/// - `config`: The sync configuration, providing the extra query parameters and proxy token.
+/// - `url`: The URL reporting the version, whose whole (trimmed) body is the version.
+///
+/// # Returns
+/// - The reported version, or an error if it couldn't be fetched.
+async fn fetch_upstream_version(
+    client: &ClientWithMiddleware,
+    config: &SyncConfig,
+    url: &str,
+) -> Result<String, Box<dyn Error>> {
+    let request_url = config.request_url(url)?;
+    let response = client
+        .get(request_url)
+        .header("X-Proxy-Token", &config.proxy_token)
+        .send()
+        .await
+        .map_err(request_error)?;
+    if !response.status().is_success() {
+        return Err(format!(
+            "Failed to check the upstream version: {}",
+            response.status()
+        )
+        .into());
+    }
+
+    let version = response.text().await?.trim().to_string();
+    if version.is_empty() {
+        return Err(EmptyResponse {
+            url: url.to_string(),
+        }
+        .into());
+    }
+    Ok(version)
+}
+
+/// Downloads the export index and decompresses it using LZMA.
+/// Unless `config.client` is set, it's requested with a client that leaves connect failures to this function, so
+/// they're retried once per `connect_retries` attempt rather than by the client's own retries as well.
+///
+/// # Arguments
+/// - `config`: The sync configuration, providing the origin URL, proxy token and client.
+/// - `previous_etag`: The ETag of the last processed index, if any.
+///
+/// # Returns
+/// A `Result` containing `ExportIndex::Unchanged` if the index ETag matches `previous_etag`,
+/// otherwise the decompressed export index and its ETag, or an error.
+pub(crate) async fn download_export_index(
+    config: &SyncConfig,
+    previous_etag: Option<&str>,
+) -> Result<ExportIndex, Box<dyn Error>> {
+    let lzma_url = index_url(&config.origin_url, &config.language)?.to_string();
+    let client = match &config.client {
+        Some(client) => client.clone(),
+        None => build_index_client(config)?,
+    };
+
+    // DNS and connection failures at the start of a run often clear up within seconds, so they get a longer backoff
+    // than the client's retries of other transient failures.
+    let request_url = config.request_url(&lzma_url)?;
+    let mut attempt = 0;
+    let response = loop {
+        let mut request = client
+            .get(request_url.clone())
+            .header("X-Proxy-Token", &config.proxy_token);
+        if let Some(etag) = previous_etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        let error = match request.send().await {
+            Ok(response) => break response,
+            Err(error) => error,
+        };
+
+        let Some((failure, message)) = connect_failure(&error) else {
+            return Err(request_error(error));
+        };
+        if attempt >= config.connect_retries {
+            return Err(request_error(error));
+        }
+        let delay = CONNECT_RETRY_DELAY * 2u32.pow(attempt);
+        attempt += 1;
+        log!(
+            "{} failed for the export index ({}), retrying in {}s ({}/{})",
+            failure,
+            message,
+            delay.as_secs(),
+            attempt,
+            config.connect_retries
+        );
+        sleep(delay).await;
+    };
+
+    let etag = response
+        .headers()
+        .get(ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
+    // Servers that ignore If-None-Match still report the ETag, so compare it ourselves too.
+    if response.status() == StatusCode::NOT_MODIFIED
+        || (etag.is_some() && etag.as_deref() == previous_etag)
+    {
+        return Ok(ExportIndex::Unchanged);
+    }
+
+    if !response.status().is_success() {
+        return Err(format!("Failed to download export index: {}", response.status()).into());
+    }
+
+    let bytes = response.bytes().await?;
+    if bytes.is_empty() {
+        return Err(EmptyResponse { url: lzma_url }.into());
+    }
+    let cursor = Cursor::new(bytes);
+
+    let mut reader = BufReader::new(cursor);
+    let mut decomp: Vec<u8> = Vec::new();
+    lzma_rs::lzma_decompress(&mut reader, &mut decomp)?;
+    let out = std::str::from_utf8(&decomp)?;
+
+    Ok(ExportIndex::Updated {
+        content: out.to_string(),
+        etag,
+    })
+}
+
+/// Downloads the export index, then the export manifest it lists, without storing either.
+///
+/// # Arguments
+/// - `client`: The HTTP client to download with.
+/// - `config`: The sync configuration, providing the server URLs.
+///
+/// # Returns
+/// - The parsed `ExportManifest`.
+pub(crate) async fn download_export_manifest(
+    client: &ClientWithMiddleware,
+    config: &SyncConfig,
+) -> Result<ExportManifest, Box<dyn Error>> {
+    let index = match download_export_index(config, None).await? {
+        ExportIndex::Updated { content, .. } => content,
+        ExportIndex::Unchanged => return Err("Export index was not returned".into()),
+    };
+    download_listed_manifest(client, config, &index).await
+}
+
+/// Downloads the export manifest listed in an export index, without storing it.
+///
+/// # Arguments
+/// - `client`: The HTTP client to download with.
+/// - `config`: The sync configuration, providing the server URLs.
+/// - `index`: The content of the export index.
+///
+/// # Returns
+/// - The parsed `ExportManifest`.
+async fn download_listed_manifest(
+    client: &ClientWithMiddleware,
+    config: &SyncConfig,
+    index: &str,
+) -> Result<ExportManifest, Box<dyn Error>> {
+    let manifest_line = index_entries(index)
+        .find(|line| line.starts_with("ExportManifest.json"))
+        .ok_or("Export index has no ExportManifest.json entry")?;
+
+    let url = manifest_url(config.export_url(), manifest_line)?;
+    let request_url = config.request_url(url.as_str())?;
+    let response = client.get(request_url).send().await?;
+    if !response.status().is_success() {
+        return Err(format!("Failed to download export manifest: {}", response.status()).into());
+    }
+    let content = response.text().await?;
+    parse_export_manifest(&RE_ESCAPES.replace_all(&content, escape_match))
+}
+
+/// Processes a single image as a sync would, writing its outputs to `sink`.
+/// No hash maps, ETags or reports are saved, so the stored state is left as it was.
+///
+/// # Arguments
+/// - `client`: The HTTP client to download with.
+/// - `config`: The sync configuration, providing the image options.
+/// - `sink`: Where the outputs of the image are written to.
+/// - `item`: The manifest item of the image.
+///
+/// # Returns
+/// - The number of bytes downloaded, and why the image was not saved, if it wasn't.
+pub(crate) async fn download_image(
+    client: ClientWithMiddleware,
+    config: SyncConfig,
+    sink: Arc<dyn OutputSink>,
+    item: &ExportManifestItem,
+) -> Result<(u64, Option<SkipReason>), Box<dyn Error>> {
+    let resource = split_string_to_resource(&item.texture_location)?;
+    let download_config = Arc::new(DownloadConfig {
+        url: texture_url(config.image_url(), &item.texture_location)?.to_string(),
+        path: config.image_dir(),
+        name: image_output_name(&item.unique_name),
+        as_text: false,
+    });
+    let resource = Resource {
+        name: item.unique_name.clone(),
+        hash: resource.hash,
+    };
+    let store = HashStore {
+        location: String::new(),
+        hashes: Mutex::new(BTreeMap::new()),
+        kinds: Mutex::new(BTreeMap::new()),
+        etag_location: String::new(),
+        etags: Mutex::new(BTreeMap::new()),
+        previous_images: None,
+        previous_manifest: None,
+    };
+
+    // Nothing cancels a single download, so the sender is dropped right away.
+    let ctx = SyncContext::new(client, config, sink, watch::channel(false).1);
+    let (bytes, skipped, _) =
+        download_file(&ctx, &store, &resource, download_config, false).await?;
+    Ok((bytes, skipped))
+}
+
+/// Checks if a resource should be downloaded by comparing its hash and initiates the download if necessary.
+///
+/// # Arguments
+/// - `ctx`: The state shared between all tasks of the run.
+/// - `store`: The hash map containing resource hashes, saved periodically as downloads complete.
+/// - `join_set`: A set of asynchronous tasks for parallel downloads, each yielding whether it succeeded.
+/// - `resource`: Resource descriptor string containing the name and hash.
+/// - `download_config`: Struct that specifies the download configuration.
+///
+/// # Returns
+/// - A tuple `(hash_updated, is_manifest)` indicating if the hash was updated and if the resource is a manifest.
+async fn check_and_download_resource(
+    ctx: &Arc<SyncContext>,
+    store: &Arc<HashStore>,
+    join_set: &mut JoinSet<bool>,
+    resource: Arc<Resource>,
+    download_config: Arc<DownloadConfig>,
+) -> Result<(bool, bool), Box<dyn Error>> {
+    // Classified for every resource, so entries from older hash files gain a kind too.
+    store
+        .kinds
+        .lock()
+        .await
+        .insert(resource.name.clone(), resource_kind(&resource.name));
+
+    let hash_lock = store.hashes.lock().await;
+    let existing_hash = hash_lock.get(&resource.name);
+    let is_manifest = resource.name == "ExportManifest.json";
+
+    // `only_sizes` repairs every current image whose original is stored, resizing the selected sizes from it.
+    let unchanged = existing_hash == Some(&resource.hash);
+    let repair = unchanged
+        && !download_config.as_text
+        && !ctx.config.only_sizes.is_empty()
+        && Path::new(&format!(
+            "{}/{}.{}",
+            &download_config.path,
+            &download_config.name,
+            ctx.config.format_for(512).extension()
+        ))
+        .is_file();
+
+    // Matching resource was found, caller should continue, unless it's revalidated with its ETag or repaired.
+    if !ctx.config.force && unchanged && !ctx.config.revalidate_unchanged && !repair {
+        ctx.report
+            .lock()
+            .await
+            .skip(&resource.name, SkipReason::Unchanged);
+        return Ok((false, is_manifest));
+    }
+
+    // A stored ETag only validates the content of the stored hash, and a forced download or a repair must not be
+    // answered with 304.
+    let conditional = !ctx.config.force && unchanged && !repair;
+
+    let is_new = existing_hash.is_none();
+    match existing_hash {
+        Some(..) if conditional || (repair && !ctx.config.force) => {}
+        None => log!(
+            "Added a new resource ➞ {} ({})",
+            resource.name,
+            resource.hash
+        ),
+        Some(existing_hash) => log!(
+            "Updated an existing resource ➞ {} ({} from {})",
+            resource.name,
+            resource.hash,
+            existing_hash
+        ),
+    }
+
+    // Frees the lock on hashes
+    drop(hash_lock);
+
+    // Smooths out request arrival, for servers that are sensitive to bursts.
+    if !ctx.config.spawn_delay.is_zero() {
+        sleep(ctx.config.spawn_delay).await;
+    }
+
+    let ctx = Arc::clone(ctx);
+    let store = Arc::clone(store);
+    let download_config = Arc::clone(&download_config);
+    ctx.emit(SyncEvent::Started(resource.name.clone()));
+    join_set.spawn(async move {
+        let result = download_file(&ctx, &store, &resource, download_config, conditional).await;
+        let result = result.map_err(|e| {
+            // Every other write would fail too, so the run stops spawning downloads.
+            if is_disk_full(e.as_ref()) {
+                let _ = ctx.disk_full.set(e.to_string());
+            }
+            e.to_string()
+        });
+        ctx.record_download(result.is_ok()).await;
+        match result {
+            Ok((bytes, skipped, etag)) => {
+                ctx.emit(SyncEvent::Completed(resource.name.clone(), bytes));
+
+                // Stored once the content is written, so the ETag never validates outputs that failed to write.
+                if let Some(etag) = etag {
+                    store.etags.lock().await.insert(resource.name.clone(), etag);
+                }
+
+                // Skipped resources are remembered too, so they aren't downloaded again next run.
+                store
+                    .hashes
+                    .lock()
+                    .await
+                    .insert(resource.name.to_owned(), resource.hash.to_owned());
+                ctx.pending_images.lock().await.remove(&resource.name);
+                store.flush(&ctx).await;
+
+                let mut report = ctx.report.lock().await;
+                if let Some(reason) = skipped {
+                    report.skip(&resource.name, reason);
+                } else if is_new {
+                    report.added.push(resource.name.to_owned());
+                } else {
+                    report.updated.push(resource.name.to_owned());
+                }
+                true
+            }
+            Err(err) => {
+                log!(
+                    "An issue occurred while downloading {} ({}): {}",
+                    resource.name,
+                    resource.hash,
+                    err
+                );
+                ctx.failures.fetch_add(1, Ordering::Relaxed);
+                ctx.report
+                    .lock()
+                    .await
+                    .fail(&resource.name, &resource.hash, &err);
+                ctx.emit(SyncEvent::Failed(resource.name.clone(), err));
+                false
+            }
+        }
+    });
+
+    Ok((true, is_manifest))
+}
+
+/// Sends the request for a resource, logging any redirect.
+/// A stored ETag that no longer matches the content is cleared, and the new one is returned to be stored once the
+/// content is written.
+///
+/// # Arguments
+/// - `ctx`: The state shared between all tasks of the run, providing the client and configuration.
+/// - `store`: The hash store of the resource, holding its ETag.
+/// - `resource`: The resource being requested.
+/// - `download_config`: Struct that specifies the download configuration.
+/// - `conditional`: Whether to send the stored ETag, so unchanged content isn't transferred again.
+///
+/// # Returns
+/// - `Ok(Ok((response, etag)))` with a successful response and its ETag, if it has one.
+/// - `Ok(Err(reason))` if the resource was not found, or not modified since its ETag was stored.
+async fn request(
+    ctx: &SyncContext,
+    store: &HashStore,
+    resource: &Resource,
+    download_config: &DownloadConfig,
+    conditional: bool,
+) -> Result<Result<(Response, Option<String>), SkipReason>, Box<dyn Error>> {
+    let url = ctx.config.request_url(&download_config.url)?;
+    let mut request = ctx.client.get(url.clone());
+    if let Some(download_timeout) = ctx.config.download_timeout {
+        request = request.timeout(download_timeout);
+    }
+    if conditional {
+        if let Some(etag) = store.etags.lock().await.get(&resource.name) {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+    }
+    let response = request.send().await.map_err(request_error)?;
+
+    // Upstream occasionally moves content paths, so make redirects visible.
+    if *response.url() != url {
+        log!("[REDIRECTED] {} ➞ {}", download_config.url, response.url());
+        ctx.report.lock().await.redirect(
+            &resource.name,
+            &download_config.url,
+            response.url().as_str(),
+        );
+    }
+
+    // Missing content won't appear by retrying, so skip it until its hash changes.
+    if response.status() == StatusCode::NOT_FOUND {
+        log!("[NOT FOUND] ➞ {}", download_config.name);
+        return Ok(Err(SkipReason::NotFound));
+    }
+
+    if response.status() == StatusCode::NOT_MODIFIED {
+        log!("[NOT MODIFIED] ➞ {}", download_config.name);
+        return Ok(Err(SkipReason::NotModified));
+    }
+
+    if !response.status().is_success() {
+        return Err(format!(
+            "Failed to download {}: {}",
+            download_config.name,
+            response.status()
+        )
+        .into());
+    }
+
+    let etag = response
+        .headers()
+        .get(ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(|etag| etag.to_string());
+
+    // Servers that ignore If-None-Match still report the ETag, so compare it ourselves too.
+    let mut etags = store.etags.lock().await;
+    if etag.is_some() && etags.get(&resource.name) == etag.as_ref() {
+        if conditional {
+            log!("[NOT MODIFIED] ➞ {}", download_config.name);
+            return Ok(Err(SkipReason::NotModified));
+        }
+    } else {
+        etags.remove(&resource.name);
+    }
+
+    Ok(Ok((response, etag)))
+}
+
+/// Downloads the content of an image, once for all manifest items that share its texture.
+///
+/// Shared textures are requested without their ETag, as other items may not have the content stored.
+///
+/// # Arguments
+/// - `ctx`: The state shared between all tasks of the run, holding the shared textures.
+/// - `store`: The image hash store, holding the ETags of images.
+/// - `resource`: The resource being downloaded.
+/// - `download_config`: Struct that specifies the download configuration.
+/// - `conditional`: Whether to send the stored ETag, unless the texture is shared.
+///
+/// # Returns
+/// - `Ok(Ok((content, etag)))` with the content of the texture and its ETag, if it has one.
+/// - `Ok(Err(reason))` if the texture was not found, or not modified since its ETag was stored.
+async fn fetch_texture(
+    ctx: &SyncContext,
+    store: &HashStore,
+    resource: &Resource,
+    download_config: &DownloadConfig,
+    conditional: bool,
+) -> Result<Result<(Vec<u8>, Option<String>), SkipReason>, Box<dyn Error>> {
+    let fetch = |conditional| async move {
+        let _permit = ctx.download_permits.acquire().await?;
+        let (response, etag) =
+            match request(ctx, store, resource, download_config, conditional).await? {
+                Ok(response) => response,
+                Err(reason) => return Ok(Err(reason)),
+            };
+        let content = response.bytes().await?;
+        if content.is_empty() {
+            return Err(EmptyResponse {
+                url: download_config.url.clone(),
+            }
+            .into());
+        }
+        Ok::<_, Box<dyn Error>>(Ok((content.to_vec(), etag)))
+    };
+
+    let shared = {
+        let mut shared_textures = ctx.shared_textures.lock().await;
+        let Some((remaining, texture)) = shared_textures.get_mut(&download_config.url) else {
+            drop(shared_textures);
+            return fetch(conditional).await;
+        };
+        let texture = Arc::clone(texture);
+        // The last item to need the texture takes it out, so its content is freed afterwards.
+        *remaining -= 1;
+        if *remaining == 0 {
+            shared_textures.remove(&download_config.url);
+        }
+        texture
+    };
+
+    let mut fetched = false;
+    let content = shared
+        .get_or_try_init(|| {
+            fetched = true;
+            fetch(false)
+        })
+        .await?;
+    if !fetched {
+        log!("[SHARED] ➞ {}", download_config.name);
+    }
+
+    Ok(content.clone())
+}
+
+/// Downloads a file from a given URL and saves it to a specified path.
+/// Optionally processes the content as text by sanitizing newlines.
+///
+/// # Arguments
+/// - `ctx`: The state shared between all tasks of the run, providing the client and configuration.
+/// - `store`: The hash store of the resource, holding its ETag.
+/// - `resource`: The resource being downloaded, passed to the `on_resource` callback.
+/// - `download_config`: Struct that specifies the download configuration.
+/// - `conditional`: Whether to send the stored ETag, so content that wasn't modified isn't processed again.
+///
+/// # Returns
+/// - `Ok((bytes, None, etag))` if the file is downloaded and saved successfully, with the size of its content and
+///   the ETag to store for it, if it has one.
+/// - `Ok((bytes, Some(reason), etag))` if the file was downloaded, but intentionally not saved, or not modified.
+async fn download_file(
+    ctx: &SyncContext,
+    store: &HashStore,
+    resource: &Resource,
+    download_config: Arc<DownloadConfig>,
+    conditional: bool,
+) -> Result<(u64, Option<SkipReason>, Option<String>), Box<dyn Error>> {
+    let config = &ctx.config;
+    let bytes;
+    let etag;
+    if download_config.as_text && !resource.name.ends_with(".json") {
+        let _permit = ctx.download_permits.acquire().await?;
+        let response = match request(ctx, store, resource, &download_config, conditional).await? {
+            Ok((response, response_etag)) => {
+                etag = response_etag;
+                response
+            }
+            Err(reason) => return Ok((0, Some(reason), None)),
+        };
+
+        // Other allowed exports aren't JSON, so they're written as provided.
+        let content = response.bytes().await?;
+        if content.is_empty() {
+            return Err(EmptyResponse {
+                url: download_config.url.clone(),
+            }
+            .into());
+        }
+        bytes = content.len() as u64;
+
+        let compression = config.store_compressed;
+        let path = format!(
+            "{}/{}{}",
+            &download_config.path,
+            &download_config.name,
+            compression.suffix()
+        );
+        let stored = compression.compress(&content)?;
+        ctx.sink.write(&path, &stored).await?;
+
+        log!("[DOWNLOADED] ➞ {}", download_config.name);
+
+        if let Some(on_resource) = &config.on_resource {
+            on_resource(resource, &content);
+        }
+    } else if download_config.as_text {
+        let _permit = ctx.download_permits.acquire().await?;
+        let response = match request(ctx, store, resource, &download_config, conditional).await? {
+            Ok((response, response_etag)) => {
+                etag = response_etag;
+                response
+            }
+            Err(reason) => return Ok((0, Some(reason), None)),
+        };
+
+        let content = response.text().await?;
+        if content.is_empty() {
+            return Err(EmptyResponse {
+                url: download_config.url.clone(),
+            }
+            .into());
+        }
+        bytes = content.len() as u64;
+        let sanitized = RE_ESCAPES.replace_all(&content, escape_match).to_string();
+
+        write_export(
+            config,
+            ctx.sink.as_ref(),
+            &download_config.path,
+            &download_config.name,
+            &sanitized,
+            true,
+        )
+        .await?;
+
+        write_source(config, &download_config.name, &sanitized).await?;
+
+        log!("[DOWNLOADED] ➞ {}", download_config.name);
+
+        if let Some(on_resource) = &config.on_resource {
+            on_resource(resource, content.as_bytes());
+        }
+    } else {
+        // A repair of some sizes resizes from the stored original, if there is one, instead of downloading it again.
+        // It's only used while it's current, i.e. when the image's hash didn't change. A changed image is written in
+        // full, as its other sizes are stale too.
+        let original_path = format!(
+            "{}/{}.{}",
+            &download_config.path,
+            &download_config.name,
+            config.format_for(512).extension()
+        );
+        let original_current =
+            store.hashes.lock().await.get(&resource.name) == Some(&resource.hash);
+        let repair = original_current && !config.only_sizes.is_empty();
+        let writes_size = |size| !repair || config.writes_size(size);
+        let cached = if writes_size(512) {
+            None
+        } else {
+            fs::read(&original_path).await.ok()
+        };
+        let fetched = cached.is_none();
+        let content = match cached {
+            Some(content) => {
+                etag = None;
+                content
+            }
+            None => match fetch_texture(ctx, store, resource, &download_config, conditional).await?
+            {
+                Ok((content, response_etag)) => {
+                    etag = response_etag;
+                    content
+                }
+                Err(reason) => return Ok((0, Some(reason), None)),
+            },
+        };
+        bytes = if fetched { content.len() as u64 } else { 0 };
+        let content_hash = blake3::hash(&content).to_hex().to_string();
+
+        // Check the format before decoding, so disallowed images cost no CPU.
+        let reader = ImageReader::new(Cursor::new(&content)).with_guessed_format()?;
+        if let Some(format) = reader.format() {
+            if fetched && !config.allows_image_format(format) {
+                log!(
+                    "[SKIPPED] ➞ {} ({:?} is not an allowed format)",
+                    download_config.name,
+                    format
+                );
+                ctx.content_hashes
+                    .lock()
+                    .await
+                    .insert(resource.name.clone(), content_hash);
+                return Ok((bytes, Some(SkipReason::DisallowedFormat), etag));
+            }
+        }
+
+        // Check the dimensions from the header before decoding, so huge images are never allocated.
+        let (width, height) = reader.into_dimensions()?;
+        if width as u64 * height as u64 > config.max_megapixels * 1_000_000 {
+            return Err(format!(
+                "Image is too large ({}x{}, limit is {} megapixels)",
+                width, height, config.max_megapixels
+            )
+            .into());
+        }
+
+        // Decoding and resizing are bound by the CPU, so they're limited apart from downloads.
+        let decode_permit = ctx.decode_permits.acquire().await?;
+        let reader = ImageReader::new(Cursor::new(&content)).with_guessed_format()?;
+
+        if let Ok(decoded) = reader.decode() {
+            let placeholder_path = format!(
+                "{}/{}.placeholder",
+                &download_config.path, &download_config.name
+            );
+            if config.store_dimensions && fetched {
+                ctx.dimensions
+                    .lock()
+                    .await
+                    .insert(resource.name.clone(), (decoded.width(), decoded.height()));
+            }
+            if config.placeholders != PlaceholderMode::Keep && is_placeholder(&decoded) {
+                log!("[PLACEHOLDER] ➞ {}", download_config.name);
+                if config.placeholders == PlaceholderMode::Marker {
+                    ctx.sink.write(&placeholder_path, &[]).await?;
+                }
+                ctx.content_hashes
+                    .lock()
+                    .await
+                    .insert(resource.name.clone(), content_hash);
+                return Ok((bytes, Some(SkipReason::Placeholder), etag));
+            }
+
+            // Computed from the decoded original, before it's flattened or resized.
+            let perceptual_hash =
+                (config.compute_phash && fetched).then(|| format!("{:016x}", dhash(&decoded)));
+            let frames = if config.split_animation_frames {
+                animation_frames(&content, &decoded, config.animation_frame_size)?
+            } else {
+                Vec::new()
+            };
+            if !frames.is_empty() {
+                log!(
+                    "[FRAMES] ➞ {} ({} frames)",
+                    download_config.name,
+                    frames.len()
+                );
+            }
+            let raw_image = to_resize_source(config, decoded)?;
+            let (width, height) = (raw_image.width(), raw_image.height());
+
+            // Save the original image, but constrain to 512x512.
+            //  Some are originally over this size, while some are originally under.
+            //  When stripping metadata, flattening or sharpening, always re-encode so the raw bytes aren't passed through.
+            //  Images stored as grayscale are re-encoded too, dropping the redundant channels.
+            let passthrough = !config.strip_metadata
+                && config.flatten_background.is_none()
+                && config.sharpen_for(512).is_none()
+                && raw_image.pixel_type() == PixelType::U8x4;
+
+            let mut jobs = Vec::new();
+            let mut written = Vec::new();
+            let original_formats = if writes_size(512) {
+                config.formats_for(512)
+            } else {
+                Vec::new()
+            };
+            for original_format in original_formats {
+                let original_path = format!(
+                    "{}/{}.{}",
+                    &download_config.path,
+                    &download_config.name,
+                    original_format.extension()
+                );
+                if width == 512
+                    && height == 512
+                    && passthrough
+                    && original_format == OutputFormat::Png
+                {
+                    ctx.sink.write(&original_path, &content).await?;
+                    if config.verify_outputs {
+                        verify_image(&original_path, 512).await?;
+                    }
+                    written.push((original_path, content.to_vec()));
+                } else {
+                    jobs.push((512, original_format, config.filter_for(512), original_path));
+                }
+            }
+
+            // With lazy sizes, variants are generated on demand by `serve` instead.
+            let sizes = if config.lazy_sizes { &[] } else { IMAGE_SIZES };
+            for size in sizes.iter().filter(|size| writes_size(**size)) {
+                for format in config.formats_for(*size) {
+                    let path = image_path(
+                        config,
+                        &download_config.path,
+                        &download_config.name,
+                        *size,
+                        format,
+                    );
+                    jobs.push((*size, format, config.filter_for(*size), path));
+                }
+            }
+
+            // Pixel-density variants for `srcset`, e.g. `{name}@2x.png`, listed in their primary format.
+            let mut srcset = Vec::new();
+            for (density, size) in &config.dpi_sizes {
+                if !writes_size(*size) {
+                    continue;
+                }
+                for (index, format) in config.formats_for(*size).into_iter().enumerate() {
+                    let file_name = format!(
+                        "{}@{}x.{}",
+                        &download_config.name,
+                        density,
+                        format.extension()
+                    );
+                    let path = format!("{}/{}", &download_config.path, file_name);
+                    if index == 0 {
+                        srcset.push((file_name, *density));
+                    }
+                    jobs.push((*size, format, config.filter_for(*size), path));
+                }
+            }
+
+            // Each frame is written like an original, e.g. `{name}.frame0.png`, with its own resized variants.
+            let mut frame_jobs = Vec::new();
+            for (index, frame) in frames.into_iter().enumerate() {
+                let frame_image = Arc::new(to_resize_source(config, frame)?);
+                let frame_name = format!("{}.frame{}", &download_config.name, index);
+                for size in [512].iter().chain(sizes) {
+                    if !writes_size(*size) {
+                        continue;
+                    }
+                    for format in config.formats_for(*size) {
+                        let path =
+                            image_path(config, &download_config.path, &frame_name, *size, format);
+                        let job = (*size, format, config.filter_for(*size), path);
+                        frame_jobs.push((Arc::clone(&frame_image), job));
+                    }
+                }
+            }
+
+            // The resizes are independent CPU work, so run them in parallel on the blocking pool.
+            let raw_image = Arc::new(raw_image);
+            let jobs = jobs
+                .into_iter()
+                .map(|job| (Arc::clone(&raw_image), job))
+                .chain(frame_jobs);
+            let semaphore = Arc::new(Semaphore::new(config.resize_parallelism.max(1)));
+            let mut resize_set = JoinSet::new();
+            for (source, (size, format, filter, path)) in jobs {
+                let permit = Arc::clone(&semaphore).acquire_owned().await?;
+                let sharpen = config.sharpen_for(size);
+                resize_set.spawn_blocking(move || {
+                    let _permit = permit;
+                    resize_image_blocking(&source, size, format, filter, sharpen)
+                        .map(|buf| (size, path, buf))
+                });
+            }
+
+            // Each output is written as soon as it's resized, as the files are independent.
+            let write_semaphore = Arc::new(Semaphore::new(config.write_parallelism.max(1)));
+            let mut write_set = JoinSet::new();
+            while let Some(result) = resize_set.join_next().await {
+                let (size, path, resized_buf) = result?.map_err(|e| e.to_string())?;
+                let permit = Arc::clone(&write_semaphore).acquire_owned().await?;
+                let sink = Arc::clone(&ctx.sink);
+                let verify_outputs = config.verify_outputs;
+                write_set.spawn(async move {
+                    let _permit = permit;
+                    // The I/O error is kept as is, so a full disk can be recognized.
+                    sink.write(&path, &resized_buf).await?;
+                    if verify_outputs {
+                        verify_image(&path, size).await.map_err(|e| e.to_string())?;
+                    }
+                    Ok::<_, Box<dyn Error + Send + Sync>>((path, resized_buf))
+                });
+            }
+            drop(decode_permit);
+
+            while let Some(result) = write_set.join_next().await {
+                written.push(result?.map_err(|e| e as Box<dyn Error>)?);
+            }
+
+            // A repair of some sizes leaves the srcset and metadata of other sizes as they were.
+            if let Some((src, _)) = srcset.first().filter(|_| !repair) {
+                let srcset_path = format!(
+                    "{}/{}.srcset.json",
+                    &download_config.path, &download_config.name
+                );
+                let candidates: Vec<String> = srcset
+                    .iter()
+                    .map(|(file_name, density)| format!("{} {}x", file_name, density))
+                    .collect();
+                let srcset_json = serde_json::json!({
+                    "src": src,
+                    "srcset": candidates.join(", "),
+                });
+                ctx.sink
+                    .write(&srcset_path, &serde_json::to_vec(&srcset_json)?)
+                    .await?;
+            }
+
+            // Provenance for attribution, tracing each image back to where it was fetched from.
+            if config.emit_image_meta && !repair {
+                let meta_path = format!(
+                    "{}/{}.meta.json",
+                    &download_config.path, &download_config.name
+                );
+                let meta_json = serde_json::json!({
+                    "unique_name": resource.name,
+                    "source_url": download_config.url,
+                    "fetched_at": SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+                    "content_hash": content_hash,
+                    "perceptual_hash": perceptual_hash,
+                    "width": width,
+                    "height": height,
+                });
+                ctx.sink
+                    .write(&meta_path, &serde_json::to_vec(&meta_json)?)
+                    .await?;
+            }
+
+            // Hashed as written, so `verify_content_on_start` can tell any damage apart from re-encoding.
+            let mut written_hashes = BTreeMap::new();
+            for (size, formats) in config.image_formats() {
+                for format in formats {
+                    let path = image_path(
+                        config,
+                        &download_config.path,
+                        &download_config.name,
+                        size,
+                        format,
+                    );
+                    if let Some((_, bytes)) = written.iter().find(|(written, _)| *written == path) {
+                        written_hashes.insert(
+                            ImageHash::output_key(size, format),
+                            blake3::hash(bytes).to_hex().to_string(),
+                        );
+                    }
+                }
+            }
+            ctx.output_hashes
+                .lock()
+                .await
+                .insert(resource.name.clone(), written_hashes);
+
+            copy_to_alias(ctx.sink.as_ref(), config, &download_config.name, &written).await?;
+            // Real art replaces the placeholder it was marked as before.
+            if config.placeholders == PlaceholderMode::Marker {
+                fs::remove_file(&placeholder_path).await.ok();
+            }
+
+            if !fetched {
+                log!("[REGENERATED] ➞ {}", download_config.name);
+                return Ok((bytes, None, etag));
+            }
+
+            ctx.content_hashes
+                .lock()
+                .await
+                .insert(resource.name.clone(), content_hash);
+            if let Some(perceptual_hash) = perceptual_hash {
+                ctx.perceptual_hashes
+                    .lock()
+                    .await
+                    .insert(resource.name.clone(), perceptual_hash);
+            }
+
+            log!("[DOWNLOADED] ➞ {}", download_config.name);
+
+            if let Some(on_resource) = &config.on_resource {
+                on_resource(resource, &content);
+            }
+        } else {
+            return Err("Invalid or corrupt image format".into());
+        }
+    }
+
+    Ok((bytes, None, etag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileSystemSink;
+    use std::io::{Read as _, Write as _};
+    use std::net::TcpListener;
+
+    #[tokio::test]
+    async fn not_modified_leaves_outputs_alone() {
+        // Answers a single request with `304 Not Modified`, sending back the request it received.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            let mut buffer = [0; 1024];
+            while !received.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).unwrap();
+                received.extend_from_slice(&buffer[..read]);
+            }
+            stream
+                .write_all(
+                    b"HTTP/1.1 304 Not Modified\r\nETag: \"abc\"\r\nConnection: close\r\n\r\n",
+                )
+                .unwrap();
+            String::from_utf8(received).unwrap()
+        });
+
+        let dir =
+            std::env::temp_dir().join(format!("warframe-exports-etag-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let original_path = dir.join("Lotus.Foo.png");
+        std::fs::write(&original_path, "stored").unwrap();
+
+        let config = SyncConfig::default();
+        let client = build_client(&config).unwrap();
+        let ctx = SyncContext::new(
+            client,
+            config,
+            Arc::new(FileSystemSink),
+            watch::channel(false).1,
+        );
+        let store = HashStore {
+            location: String::new(),
+            hashes: Mutex::new(BTreeMap::from([(
+                "/Lotus/Foo".to_string(),
+                "00_abc".to_string(),
+            )])),
+            kinds: Mutex::new(BTreeMap::new()),
+            etag_location: String::new(),
+            etags: Mutex::new(BTreeMap::from([(
+                "/Lotus/Foo".to_string(),
+                "\"abc\"".to_string(),
+            )])),
+            previous_images: None,
+            previous_manifest: None,
+        };
+        let resource = Resource {
+            name: "/Lotus/Foo".to_string(),
+            hash: "00_abc".to_string(),
+        };
+        let download_config = Arc::new(DownloadConfig {
+            url: format!(
+                "http://127.0.0.1:{}/PublicExport/Lotus/Foo.png!00_abc",
+                port
+            ),
+            path: dir.to_str().unwrap().to_string(),
+            name: "Lotus.Foo".to_string(),
+            as_text: false,
+        });
+
+        let result = download_file(&ctx, &store, &resource, download_config, true)
+            .await
+            .unwrap();
+        assert_eq!(result, (0, Some(SkipReason::NotModified), None));
+        assert!(server.join().unwrap().contains("if-none-match: \"abc\""));
+        assert_eq!(std::fs::read_to_string(&original_path).unwrap(), "stored");
+        assert_eq!(
+            store.etags.lock().await.get("/Lotus/Foo").unwrap(),
+            "\"abc\""
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,116 @@
+use reqwest::Url;
+use std::error::Error;
+
+use crate::{LZMA_URL_PATH, MANIFEST_PATH, PUBLIC_EXPORT_PATH};
+
+/// The URL of the compressed export index of a language.
+///
+/// # Arguments
+/// - `origin`: The origin server, e.g. `https://origin.warframe.com`.
+/// - `language`: The language code of the index, e.g. `en`.
+///
+/// # Returns
+/// - The URL, e.g. `https://origin.warframe.com/PublicExport/index_en.txt.lzma`.
+pub fn index_url(origin: &str, language: &str) -> Result<Url, Box<dyn Error>> {
+    join(origin, &[&LZMA_URL_PATH.replace("{language}", language)])
+}
+
+/// The URL of an export listed in the export index.
+///
+/// # Arguments
+/// - `content`: The content server, e.g. `https://content.warframe.com`.
+/// - `line`: The entry of the export index, e.g. `ExportWeapons_en.json!00_abc`.
+///
+/// # Returns
+/// - The URL, e.g. `https://content.warframe.com/PublicExport/Manifest/ExportWeapons_en.json!00_abc`.
+pub fn manifest_url(content: &str, line: &str) -> Result<Url, Box<dyn Error>> {
+    join(content, &[MANIFEST_PATH, line])
+}
+
+/// The URL of an image listed in the export manifest.
+///
+/// # Arguments
+/// - `content`: The content server, e.g. `https://content.warframe.com`.
+/// - `texture_location`: The texture location of the manifest item, e.g. `/Lotus/Interface/Icons/Foo.png!00_abc`.
+///
+/// # Returns
+/// - The URL, e.g. `https://content.warframe.com/PublicExport/Lotus/Interface/Icons/Foo.png!00_abc`.
+pub fn texture_url(content: &str, texture_location: &str) -> Result<Url, Box<dyn Error>> {
+    join(content, &[PUBLIC_EXPORT_PATH, texture_location])
+}
+
+/// Appends paths to the path of a base URL, with exactly one slash between segments, whether or not the
+/// base ends with one or the paths start with one. A query string of the base is kept.
+///
+/// # Arguments
+/// - `base`: The base URL, which may have a path of its own, e.g. behind a proxy.
+/// - `paths`: The paths to append, in order.
+///
+/// # Returns
+/// - The joined URL, or an error if `base` is not a valid base URL.
+fn join(base: &str, paths: &[&str]) -> Result<Url, Box<dyn Error>> {
+    let mut url = Url::parse(base)?;
+    url.path_segments_mut()
+        .map_err(|_| format!("{} can't be used as a base URL", base))?
+        .pop_if_empty()
+        .extend(
+            paths
+                .iter()
+                .flat_map(|path| path.split('/'))
+                .filter(|segment| !segment.is_empty()),
+        );
+    Ok(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_with_and_without_a_trailing_slash() {
+        let expected = "https://origin.warframe.com/PublicExport/index_en.txt.lzma";
+        assert_eq!(
+            index_url("https://origin.warframe.com", "en")
+                .unwrap()
+                .as_str(),
+            expected
+        );
+        assert_eq!(
+            index_url("https://origin.warframe.com/", "en")
+                .unwrap()
+                .as_str(),
+            expected
+        );
+    }
+
+    #[test]
+    fn keeps_the_path_of_the_base() {
+        let url = manifest_url(
+            "https://content.example.com/proxy/",
+            "ExportWeapons_en.json!00_abc",
+        )
+        .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://content.example.com/proxy/PublicExport/Manifest/ExportWeapons_en.json!00_abc"
+        );
+    }
+
+    #[test]
+    fn keeps_the_query_of_the_base() {
+        let url = texture_url(
+            "https://content.example.com/?key=value",
+            "/Lotus/Foo.png!00_abc",
+        )
+        .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://content.example.com/PublicExport/Lotus/Foo.png!00_abc?key=value"
+        );
+    }
+
+    #[test]
+    fn rejects_a_base_without_a_path() {
+        assert!(index_url("mailto:someone@example.com", "en").is_err());
+    }
+}